@@ -0,0 +1,54 @@
+//! # Idle benchmark
+//! Measures how much progress an unrelated task makes on a current-thread runtime while a
+//! [Driver] sits idle. The receive loop polls `td_receive` on its own thread, so the ticker below
+//! should land close to one tick per millisecond. If the loop ever ends up on a runtime worker
+//! again, the ticker stalls for a full poll interval at a time.
+use std::time::Duration;
+use std::time::Instant;
+use tdlib_driver::*;
+
+/// Length of a single measurement
+const WINDOW: Duration = Duration::from_secs(3);
+
+/// Interval of the ticker task
+const TICK: Duration = Duration::from_millis(1);
+
+/// Counts ticks of a [tokio::time::interval] for [WINDOW]
+async fn ticks() -> u64 {
+    let mut interval = tokio::time::interval(TICK);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let start = Instant::now();
+    let mut ticks = 0u64;
+    while start.elapsed() < WINDOW {
+        interval.tick().await;
+        ticks += 1;
+    }
+    ticks
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        // Baseline without a driver
+        let baseline = ticks().await;
+
+        // Same measurement with an idle driver polling in the background
+        let driver = Driver::new(None);
+        driver.getAuthorizationState().await.unwrap();
+        let idle = ticks().await;
+
+        let expected = WINDOW.as_millis() as u64 / TICK.as_millis() as u64;
+        println!("expected ticks:    {expected}");
+        println!("baseline ticks:    {baseline}");
+        println!("idle driver ticks: {idle}");
+        println!(
+            "progress retained: {:.1}%",
+            idle as f64 / baseline.max(1) as f64 * 100f64
+        );
+    });
+}
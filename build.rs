@@ -13,19 +13,10 @@ fn docker_tdlib(out_dir: &str) {
 
 // fn parse_tdlib_api() {}
 
-fn main() {
-    // Tell Cargo to rebuild if the build scripts change
-    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    println!("cargo:rerun-if-changed={manifest_dir}/build.rs");
-    println!("cargo:rerun-if-changed={manifest_dir}/build.sh");
-    println!("cargo:rerun-if-changed={manifest_dir}/headers.h");
-    println!("cargo:rerun-if-changed={manifest_dir}/build/Dockerfile");
-
-    // Get out_dir
-    let out_dir = env::var("OUT_DIR").unwrap();
-
+/// Builds TDLib, links `libtdjson` and generates the bindings to it
+fn link_tdjson(out_dir: &str) {
     // Build library
-    docker_tdlib(&out_dir);
+    docker_tdlib(out_dir);
 
     // Run linkers
     let include_dir = format!("{out_dir}/bin/include");
@@ -92,16 +83,41 @@ fn main() {
             .write_to_file(bindings_file.as_path())
             .expect("Couldn't write bindings!");
     }
+}
 
-    // Open generated td_lib
-    let td_api = std::fs::read_to_string(
-        out_path
-            .join("td")
-            .join("generate")
-            .join("scheme")
-            .join("td_api.tl"),
-    )
-    .unwrap();
+fn main() {
+    // Tell Cargo to rebuild if the build scripts change
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed={manifest_dir}/build.rs");
+    println!("cargo:rerun-if-changed={manifest_dir}/build.sh");
+    println!("cargo:rerun-if-changed={manifest_dir}/headers.h");
+    println!("cargo:rerun-if-changed={manifest_dir}/build/Dockerfile");
+
+    // Get out_dir
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_path = PathBuf::from(&out_dir);
+
+    // Without `tdjson` nothing is linked and the API is generated from the bundled schema
+    let td_api = match env::var_os("CARGO_FEATURE_TDJSON") {
+        Some(_) => {
+            link_tdjson(&out_dir);
+
+            // Open generated td_lib
+            std::fs::read_to_string(
+                out_path
+                    .join("td")
+                    .join("generate")
+                    .join("scheme")
+                    .join("td_api.tl"),
+            )
+            .unwrap()
+        }
+        None => {
+            let schema = format!("{manifest_dir}/td_api_rs/td_api.md");
+            println!("cargo:rerun-if-changed={schema}");
+            std::fs::read_to_string(schema).unwrap()
+        }
+    };
 
     let td_api_path = out_path.join("td_api.rs");
 
@@ -0,0 +1,421 @@
+use super::*;
+
+/// Number of attempts per step when the [Authenticator] does not say otherwise
+pub const DEFAULT_ATTEMPTS: usize = 3;
+
+#[allow(async_fn_in_trait)]
+/// # Authenticator
+/// Supplies the credentials [authorize] asks for while it walks a client through TDLib's
+/// authorization states. Every prompt receives the error of the previous attempt at the same step
+/// (if any), so a wrong code or password can be asked for again; returning [None] aborts the
+/// flow with [AuthError::Aborted].
+///
+/// *Note*: to log in as a bot, return the token from [Authenticator::bot_token]; the phone number
+/// is then never asked for.
+pub trait Authenticator {
+    /// Parameters for `setTdlibParameters`, asked for in `authorizationStateWaitTdlibParameters`
+    async fn tdlib_parameters(&self) -> Option<TdlibParameters>;
+
+    /// Bot token to log in with, asked for in `authorizationStateWaitPhoneNumber`
+    async fn bot_token(&self, previous: Option<&AuthError>) -> Option<String> {
+        let _ = previous;
+        None
+    }
+
+    /// Phone number to log in with, asked for in `authorizationStateWaitPhoneNumber`
+    async fn phone_number(&self, previous: Option<&AuthError>) -> Option<String>;
+
+    /// Authentication code sent to the user, asked for in `authorizationStateWaitCode`
+    async fn code(
+        &self,
+        info: &authenticationCodeInfo,
+        previous: Option<&AuthError>,
+    ) -> Option<String>;
+
+    /// 2-step verification password, asked for in `authorizationStateWaitPassword`
+    async fn password(&self, hint: &str, previous: Option<&AuthError>) -> Option<String>;
+
+    /// Email address of the user, asked for in `authorizationStateWaitEmailAddress`
+    async fn email_address(&self, previous: Option<&AuthError>) -> Option<String> {
+        let _ = previous;
+        None
+    }
+
+    /// Code sent to the email address of the user, asked for in
+    /// `authorizationStateWaitEmailCode`
+    async fn email_code(
+        &self,
+        info: &emailAddressAuthenticationCodeInfo,
+        previous: Option<&AuthError>,
+    ) -> Option<String> {
+        let _ = (info, previous);
+        None
+    }
+
+    /// Number of attempts per step before [authorize] gives up
+    fn attempts(&self) -> usize {
+        DEFAULT_ATTEMPTS
+    }
+}
+
+#[derive(Debug)]
+/// Errors of [authorize]. The `Invalid*` variants carry TDLib's error message and are retried
+/// until [Authenticator::attempts] runs out.
+pub enum AuthError {
+    InvalidPhoneNumber(String),
+    InvalidBotToken(String),
+    InvalidCode(String),
+    InvalidPassword(String),
+    InvalidEmailAddress(String),
+
+    /// The authentication code has expired; a new one has to be requested
+    CodeExpired(String),
+
+    /// The [Authenticator] returned [None]
+    Aborted,
+
+    /// The flow reached a state it does not handle, e.g. registration of a new account
+    Unsupported(AuthorizationState),
+
+    /// The client is logging out, closing or closed
+    Closed,
+
+    /// Any other failure of a request
+    Failure(Failure),
+}
+
+impl AuthError {
+    /// Whether asking for the same credential again may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidPhoneNumber(_)
+                | Self::InvalidBotToken(_)
+                | Self::InvalidCode(_)
+                | Self::InvalidPassword(_)
+                | Self::InvalidEmailAddress(_)
+        )
+    }
+
+    /// Classifies a TDLib error by its message
+    fn classify(message: &str) -> Option<Self> {
+        let error = match message {
+            message if message.contains("PHONE_NUMBER") => Self::InvalidPhoneNumber,
+            message if message.contains("ACCESS_TOKEN") => Self::InvalidBotToken,
+            message if message.contains("CODE_EXPIRED") => Self::CodeExpired,
+            message if message.contains("CODE_INVALID") || message.contains("CODE_EMPTY") => {
+                Self::InvalidCode
+            }
+            message if message.contains("PASSWORD_HASH_INVALID") => Self::InvalidPassword,
+            message if message.contains("EMAIL_INVALID") => Self::InvalidEmailAddress,
+            _ => None?,
+        };
+        Some(error(message.to_string()))
+    }
+}
+
+impl From<Failure> for AuthError {
+    fn from(failure: Failure) -> Self {
+        if let Failure::TdLib(Error::error(td_api::error { code: 400, message })) = &failure {
+            if let Some(err) = Self::classify(message) {
+                return err;
+            }
+        }
+        Self::Failure(failure)
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPhoneNumber(message) => write!(f, "invalid phone number: {message}"),
+            Self::InvalidBotToken(message) => write!(f, "invalid bot token: {message}"),
+            Self::InvalidCode(message) => write!(f, "invalid code: {message}"),
+            Self::InvalidPassword(message) => write!(f, "invalid password: {message}"),
+            Self::InvalidEmailAddress(message) => write!(f, "invalid email address: {message}"),
+            Self::CodeExpired(message) => write!(f, "code expired: {message}"),
+            Self::Aborted => write!(f, "authorization aborted"),
+            Self::Unsupported(state) => write!(f, "unsupported authorization state: {state:?}"),
+            Self::Closed => write!(f, "client is closed"),
+            Self::Failure(failure) => write!(f, "{failure}"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Failure(failure) => Some(failure),
+            _ => None,
+        }
+    }
+}
+
+/// # Authorize
+/// Walks the client of `driver` through TDLib's authorization states until
+/// `authorizationStateReady`, asking `authenticator` for whatever the current state requires.
+/// Reacts to `updateAuthorizationState`, so it can be started at any point of the flow.
+/// # Example
+/// ```ignore
+/// struct Bot(TdlibParameters, String);
+///
+/// impl Authenticator for Bot {
+///     async fn tdlib_parameters(&self) -> Option<TdlibParameters> {
+///         Some(self.0.clone())
+///     }
+///     async fn bot_token(&self, previous: Option<&AuthError>) -> Option<String> {
+///         previous.is_none().then(|| self.1.clone())
+///     }
+///     // ...
+/// }
+///
+/// authorize(&driver, &Bot(parameters, token)).await?;
+/// ```
+pub async fn authorize<Auth: Authenticator>(
+    driver: &Driver,
+    authenticator: &Auth,
+) -> Result<(), AuthError> {
+    // Subscribe before asking for the state so that no transition is missed
+    let mut updates = driver.subscribe_to(["updateAuthorizationState"]);
+    let mut state = driver.getAuthorizationState().await?;
+
+    loop {
+        if state == AuthorizationState::authorizationStateReady {
+            return Result::Ok(());
+        }
+
+        // Handle the state, asking again for as long as the error allows it
+        let mut previous = None;
+        for attempt in 1..=authenticator.attempts().max(1) {
+            match step(driver, authenticator, &state, previous.as_ref()).await {
+                Result::Ok(()) => break,
+                Err(err) if err.is_retryable() && attempt < authenticator.attempts() => {
+                    previous = Some(err)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Wait for the state to move on
+        // NOTE: updates queued before the state was handled may carry states it has already
+        // superseded, so an update only prompts asking for the current state
+        state = loop {
+            updates.recv().await.ok_or(AuthError::Closed)?;
+            let current = driver.getAuthorizationState().await?;
+            if current != state {
+                break current;
+            }
+        };
+    }
+}
+
+/// Handles a single authorization `state`
+async fn step<Auth: Authenticator>(
+    driver: &Driver,
+    authenticator: &Auth,
+    state: &AuthorizationState,
+    previous: Option<&AuthError>,
+) -> Result<(), AuthError> {
+    match state {
+        AuthorizationState::authorizationStateWaitTdlibParameters => {
+            let parameters = authenticator
+                .tdlib_parameters()
+                .await
+                .ok_or(AuthError::Aborted)?;
+            driver.send::<_, Ok>(parameters).await?;
+        }
+        AuthorizationState::authorizationStateWaitPhoneNumber => {
+            match authenticator.bot_token(previous).await {
+                Some(token) => driver.checkAuthenticationBotToken(token).await?,
+                // A bot whose token was rejected does not fall back to a phone number
+                None if matches!(previous, Some(AuthError::InvalidBotToken(_))) => {
+                    Err(AuthError::Aborted)?
+                }
+                None => {
+                    let phone_number = authenticator
+                        .phone_number(previous)
+                        .await
+                        .ok_or(AuthError::Aborted)?;
+                    driver
+                        .setAuthenticationPhoneNumber(phone_number, None)
+                        .await?
+                }
+            };
+        }
+        AuthorizationState::authorizationStateWaitCode(authorizationStateWaitCode {
+            code_info,
+        }) => {
+            let code = authenticator
+                .code(code_info, previous)
+                .await
+                .ok_or(AuthError::Aborted)?;
+            driver.checkAuthenticationCode(code).await?;
+        }
+        AuthorizationState::authorizationStateWaitPassword(authorizationStateWaitPassword {
+            password_hint,
+            ..
+        }) => {
+            let password = authenticator
+                .password(password_hint, previous)
+                .await
+                .ok_or(AuthError::Aborted)?;
+            driver.checkAuthenticationPassword(password).await?;
+        }
+        AuthorizationState::authorizationStateWaitEmailAddress(_) => {
+            let email_address = authenticator
+                .email_address(previous)
+                .await
+                .ok_or(AuthError::Aborted)?;
+            driver.setAuthenticationEmailAddress(email_address).await?;
+        }
+        AuthorizationState::authorizationStateWaitEmailCode(authorizationStateWaitEmailCode {
+            code_info,
+            ..
+        }) => {
+            let code = authenticator
+                .email_code(code_info, previous)
+                .await
+                .ok_or(AuthError::Aborted)?;
+            let code = EmailAddressAuthentication::emailAddressAuthenticationCode(
+                emailAddressAuthenticationCode { code },
+            );
+            driver.checkAuthenticationEmailCode(code).await?;
+        }
+        AuthorizationState::authorizationStateLoggingOut
+        | AuthorizationState::authorizationStateClosing
+        | AuthorizationState::authorizationStateClosed => Err(AuthError::Closed)?,
+        state => Err(AuthError::Unsupported(state.clone()))?,
+    }
+
+    Result::Ok(())
+}
+
+#[test]
+/// TDLib errors of the login methods are classified by their message
+fn auth_error_classification() {
+    let failure = |message: &str| {
+        Failure::TdLib(Error::error(td_api::error {
+            code: 400,
+            message: message.into(),
+        }))
+    };
+
+    let err = AuthError::from(failure("PHONE_CODE_INVALID"));
+    assert!(matches!(err, AuthError::InvalidCode(_)));
+    assert!(err.is_retryable());
+
+    let err = AuthError::from(failure("PHONE_CODE_EXPIRED"));
+    assert!(matches!(err, AuthError::CodeExpired(_)));
+    assert!(!err.is_retryable());
+
+    let err = AuthError::from(failure("PASSWORD_HASH_INVALID"));
+    assert!(matches!(err, AuthError::InvalidPassword(_)));
+
+    let err = AuthError::from(failure("PHONE_NUMBER_INVALID"));
+    assert!(matches!(err, AuthError::InvalidPhoneNumber(_)));
+
+    let err = AuthError::from(failure("ACCESS_TOKEN_INVALID"));
+    assert!(matches!(err, AuthError::InvalidBotToken(_)));
+
+    let err = AuthError::from(failure("Unexpected setTdlibParameters"));
+    assert!(matches!(err, AuthError::Failure(_)));
+}
+
+#[tokio::test]
+/// The flow is walked from the parameters to ready, and states it has superseded are not handled
+/// again
+async fn authorize_flow() {
+    struct User;
+
+    impl Authenticator for User {
+        async fn tdlib_parameters(&self) -> Option<TdlibParameters> {
+            Some(TdlibParameters::default())
+        }
+        async fn phone_number(&self, _: Option<&AuthError>) -> Option<String> {
+            Some("+1555".into())
+        }
+        async fn code(
+            &self,
+            _: &authenticationCodeInfo,
+            previous: Option<&AuthError>,
+        ) -> Option<String> {
+            Some(if previous.is_none() { "00000" } else { "12345" }.into())
+        }
+        async fn password(&self, _: &str, _: Option<&AuthError>) -> Option<String> {
+            None
+        }
+    }
+
+    let state = |kind: &str| match kind {
+        "authorizationStateWaitCode" => serde_json::json!({ "@type": kind, "code_info": {
+            "@type": "authenticationCodeInfo", "phone_number": "+1555", "timeout": 60,
+            "type": { "@type": "authenticationCodeTypeSms", "length": 5 },
+        }}),
+        _ => serde_json::json!({ "@type": kind }),
+    };
+    let update = move |kind: &str| {
+        let state = state(kind);
+        serde_json::json!({ "@type": "updateAuthorizationState", "authorization_state": state })
+    };
+    let ok = serde_json::json!({ "@type": "ok" });
+    let error =
+        |message: &str| serde_json::json!({ "@type": "error", "code": 400, "message": message });
+
+    // Scripted TDLib: every step moves the state on, and the phone number step also repeats a
+    // stale state
+    let transport = MemoryTransport::new();
+    let current = Arc::new(Mutex::new("authorizationStateWaitTdlibParameters"));
+    transport.respond_with({
+        let transport = Arc::downgrade(&transport);
+        let current = current.clone();
+        move |request| {
+            let transport = transport.upgrade()?;
+            let mut current = current.lock().unwrap();
+            let (expected, next) = match request["@type"].as_str()? {
+                "getAuthorizationState" => return Some(state(&current)),
+                "setTdlibParameters" => (
+                    "authorizationStateWaitTdlibParameters",
+                    "authorizationStateWaitPhoneNumber",
+                ),
+                "setAuthenticationPhoneNumber" => {
+                    transport.push(1, update("authorizationStateWaitTdlibParameters"));
+                    (
+                        "authorizationStateWaitPhoneNumber",
+                        "authorizationStateWaitCode",
+                    )
+                }
+                "checkAuthenticationCode" if request["code"] == "00000" => {
+                    return Some(error("PHONE_CODE_INVALID"))
+                }
+                "checkAuthenticationCode" => {
+                    ("authorizationStateWaitCode", "authorizationStateReady")
+                }
+                _ => return None,
+            };
+            if *current != expected {
+                return Some(error(&format!("Unexpected {}", request["@type"])));
+            }
+            *current = next;
+            transport.push(1, update(next));
+            Some(ok.clone())
+        }
+    });
+    let driver = ClientManager::new(transport.clone()).client(None);
+
+    authorize(&driver, &User).await.unwrap();
+    let steps: Vec<_> = transport
+        .requests()
+        .into_iter()
+        .map(|(_, request)| request["@type"].as_str().unwrap().to_string())
+        .filter(|method| method != "getAuthorizationState")
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            "setTdlibParameters",
+            "setAuthenticationPhoneNumber",
+            "checkAuthenticationCode",
+            "checkAuthenticationCode"
+        ]
+    );
+}
@@ -0,0 +1,240 @@
+use super::*;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Timeout of a request when nothing else has been configured
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request settings of a [Driver], fixed when the [Driver] is built
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// Timeout of requests without an override
+    pub(crate) timeout: Duration,
+
+    /// Timeout overrides keyed by the `@type` of the payload, e.g. `downloadFile`
+    pub(crate) method_timeouts: HashMap<String, Duration>,
+
+    /// Number of updates buffered for subscribers
+    pub(crate) update_capacity: usize,
+
+    /// Which failed requests are repeated
+    pub(crate) retry: RetryPolicy,
+
+    /// Limits that hold requests back before they are sent
+    pub(crate) rate_limiter: Option<RateLimiter>,
+
+    /// Store of the entities pushed by TDLib, fed from every update
+    pub(crate) state: Option<State>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            method_timeouts: HashMap::new(),
+            update_capacity: DEFAULT_UPDATE_CAPACITY,
+            retry: RetryPolicy::none(),
+            rate_limiter: None,
+            state: None,
+        }
+    }
+}
+
+impl Config {
+    /// Timeout of a request for `method`
+    pub(crate) fn timeout(&self, method: Option<&str>) -> Duration {
+        method
+            .and_then(|method| self.method_timeouts.get(method))
+            .copied()
+            .unwrap_or(self.timeout)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "@type", rename = "setTdlibParameters")]
+/// # TdlibParameters
+/// Payload of `setTdlibParameters`, see [Api::setTdlibParameters] for a description of the
+/// fields. Handed to [DriverBuilder::tdlib_parameters] to have it applied on startup.
+pub struct TdlibParameters {
+    pub use_test_dc: bool,
+    pub database_directory: String,
+    pub files_directory: String,
+    pub database_encryption_key: String,
+    pub use_file_database: bool,
+    pub use_chat_info_database: bool,
+    pub use_message_database: bool,
+    pub use_secret_chats: bool,
+    pub api_id: i32,
+    pub api_hash: String,
+    pub system_language_code: String,
+    pub device_model: String,
+    pub system_version: String,
+    pub application_version: String,
+}
+
+/// # Driver Builder
+/// Runtime configuration of a [Driver]:
+/// - [DriverBuilder::timeout]: default timeout of a request
+/// - [DriverBuilder::method_timeout]: timeout of every request to one method
+/// - [DriverBuilder::poll_interval]: how long the receive loop blocks in `td_receive`
+/// - [DriverBuilder::notifier]: notification handler of the client
+/// - [DriverBuilder::update_capacity]: number of updates buffered for subscribers
+/// - [DriverBuilder::retry]: which failed requests are repeated
+/// - [DriverBuilder::rate_limit]: how many requests are sent per second
+/// - [DriverBuilder::state]: local copy of the users, chats and files pushed by TDLib
+/// - [DriverBuilder::log_verbosity]: initial TDLib log verbosity
+/// - [DriverBuilder::log_stream]: where TDLib writes its own log
+/// - [DriverBuilder::tdlib_parameters]: `setTdlibParameters` payload applied on startup
+///
+/// *Note*: per-call timeouts are set with [Driver::with_timeout].
+/// # Example
+/// ```ignore
+/// let driver = DriverBuilder::new()
+///     .timeout(Duration::from_secs(5))
+///     .method_timeout("downloadFile", Duration::from_secs(300))
+///     .log_verbosity(1)
+///     .build()
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct DriverBuilder {
+    manager: Option<ClientManager>,
+    config: Config,
+    poll_interval: Option<Duration>,
+    notifier: Option<NotificationSink>,
+    log_verbosity: Option<i32>,
+    log_stream: Option<LogStream>,
+    tdlib_parameters: Option<TdlibParameters>,
+}
+
+impl DriverBuilder {
+    /// Creates a builder with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the client with `manager` instead of [ClientManager::global].
+    ///
+    /// *Note*: required without the `tdjson` feature, which provides the global manager;
+    /// [DriverBuilder::build] fails with [ReceiverError::NoManager] otherwise.
+    pub fn manager(mut self, manager: ClientManager) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+
+    /// Default timeout of a request; [DEFAULT_TIMEOUT] if not set
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Timeout of every request to `method`, e.g. `downloadFile`
+    pub fn method_timeout<Method: Into<String>>(
+        mut self,
+        method: Method,
+        timeout: Duration,
+    ) -> Self {
+        self.config.method_timeouts.insert(method.into(), timeout);
+        self
+    }
+
+    /// How long the receive loop blocks in `td_receive` before checking for a stop signal.
+    ///
+    /// *Note*: the receive loop is shared by all clients of the [ClientManager], so this applies
+    /// to all of them.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = Some(poll_interval);
+        self
+    }
+
+    /// Notification handler of the client: an unbounded [Notifier], or a [BoundedNotifier] to cap
+    /// the notifications queued for a slow consumer, see `notifier::bounded`
+    pub fn notifier<Sink: Into<NotificationSink>>(mut self, notifier: Sink) -> Self {
+        self.notifier = Some(notifier.into());
+        self
+    }
+
+    /// Number of updates buffered for [Subscription]s before slow subscribers start missing
+    /// updates; [DEFAULT_UPDATE_CAPACITY] if not set
+    pub fn update_capacity(mut self, capacity: usize) -> Self {
+        self.config.update_capacity = capacity.max(1);
+        self
+    }
+
+    /// Repeats failed requests as `policy` allows; by default nothing is repeated
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry = policy;
+        self
+    }
+
+    /// Holds requests back until they fit into the limits of `limiter`; by default nothing is
+    /// held back
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.config.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Keeps `state` up to date from the updates of the client; by default no state is kept
+    pub fn state(mut self, state: State) -> Self {
+        self.config.state = Some(state);
+        self
+    }
+
+    /// TDLib log verbosity to set on startup, see `logging::set_verbosity`
+    pub fn log_verbosity(mut self, level: i32) -> Self {
+        self.log_verbosity = Some(level);
+        self
+    }
+
+    /// Where TDLib writes its own log, see `logging::set_stream`. Log messages are forwarded to
+    /// [tracing] regardless.
+    pub fn log_stream(mut self, stream: LogStream) -> Self {
+        self.log_stream = Some(stream);
+        self
+    }
+
+    /// `setTdlibParameters` payload to apply on startup
+    pub fn tdlib_parameters(mut self, parameters: TdlibParameters) -> Self {
+        self.tdlib_parameters = Some(parameters);
+        self
+    }
+
+    /// # Build
+    /// Creates the client and applies the startup settings in order: log verbosity, log stream,
+    /// then `setTdlibParameters`.
+    pub async fn build(self) -> Result<Driver, Failure> {
+        let Self {
+            manager,
+            config,
+            poll_interval,
+            notifier,
+            log_verbosity,
+            log_stream,
+            tdlib_parameters,
+        } = self;
+
+        #[cfg(feature = "tdjson")]
+        let manager = manager.unwrap_or_else(ClientManager::global);
+        #[cfg(not(feature = "tdjson"))]
+        let manager = manager.ok_or(Failure::Receiver(ReceiverError::NoManager))?;
+        if let Some(poll_interval) = poll_interval {
+            manager.set_poll_interval(poll_interval);
+        }
+
+        let driver = manager.client_with(notifier, config);
+
+        if let Some(level) = log_verbosity {
+            manager.setLogVerbosityLevel(level)?;
+        }
+
+        if let Some(stream) = log_stream {
+            manager.setLogStream(stream)?;
+        }
+
+        if let Some(parameters) = tdlib_parameters {
+            driver.send::<_, Ok>(parameters).await?;
+        }
+
+        Result::Ok(driver)
+    }
+}
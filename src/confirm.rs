@@ -0,0 +1,508 @@
+use super::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+/// Errors of the `*_and_confirm` functions of [Driver], e.g. [Driver::send_and_confirm]
+pub enum SendError {
+    /// TDLib accepted the message, but failed to send it (`updateMessageSendFailed`)
+    Failed {
+        /// The message that failed to be sent
+        message: Box<message>,
+
+        /// Cause of the failure
+        error: td_api::error,
+
+        /// Whether the message can be re-sent with `resendMessages`
+        can_retry: bool,
+
+        /// Time left before the message can be re-sent
+        retry_after: Duration,
+    },
+
+    /// Updates were skipped because the subscriber fell behind, and the confirmation of the
+    /// message with `message_id` was among them
+    Unconfirmed { chat_id: i64, message_id: i64 },
+
+    /// The [Driver] went away before the message was confirmed
+    Closed,
+
+    /// The request to send the message failed
+    Failure(Arc<Failure>),
+}
+
+impl SendError {
+    /// # Retry After
+    /// How long to wait before sending the message again, if it can be sent again at all: the
+    /// hint of TDLib for a [SendError::Failed] message, or the flood wait of a [Failure]
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Failed {
+                can_retry: true,
+                retry_after,
+                ..
+            } => Some(*retry_after),
+            Self::Failure(failure) => match failure.kind() {
+                ErrorKind::FloodWait { retry_after } => Some(retry_after),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Failure of a message that TDLib reported as failed right away
+    fn failed(message: message, error: td_api::error) -> Self {
+        let (can_retry, retry_after) = match &message.sending_state {
+            Some(MessageSendingState::messageSendingStateFailed(state)) => {
+                (state.can_retry, state.retry_after)
+            }
+            _ => (false, 0.0),
+        };
+        Self::Failed {
+            message: Box::new(message),
+            error,
+            can_retry,
+            retry_after: Duration::try_from_secs_f64(retry_after).unwrap_or_default(),
+        }
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Failed { error, .. } => {
+                write!(
+                    f,
+                    "failed to send message: {} {}",
+                    error.code, error.message
+                )
+            }
+            Self::Unconfirmed {
+                chat_id,
+                message_id,
+            } => write!(
+                f,
+                "confirmation of message {message_id} in chat {chat_id} was missed"
+            ),
+            Self::Closed => write!(f, "client is closed"),
+            Self::Failure(failure) => write!(f, "{failure}"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Failure(failure) => Some(failure.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Failure> for SendError {
+    fn from(value: Failure) -> Self {
+        Self::Failure(Arc::new(value))
+    }
+}
+
+/// `messages` response of methods that answer with `null` for each message they could not send
+#[derive(serde::Deserialize)]
+struct Sent {
+    #[serde(default)]
+    messages: Vec<Option<message>>,
+}
+
+impl Sent {
+    /// The messages that were sent
+    fn sent(self) -> Vec<message> {
+        self.messages.into_iter().flatten().collect()
+    }
+}
+
+impl Driver {
+    /// # Send And Confirm
+    /// Sends a message with `sendMessage` and waits until TDLib has confirmed it. `sendMessage`
+    /// answers with a temporary message that is still being sent; this waits for the
+    /// `updateMessageSendSucceeded` or `updateMessageSendFailed` of that message and returns the
+    /// message as it was sent, with its final id.
+    ///
+    /// Arguments are those of `sendMessage`.
+    ///
+    /// *Note*: use [SendError::retry_after] to find out if and when a failed message may be sent
+    /// again.
+    /// # Example
+    /// ```ignore
+    /// let content = InputMessageContent::inputMessageText(inputMessageText { .. });
+    /// match driver.send_and_confirm(chat_id, 0, None, None, None, content).await {
+    ///     Ok(message) => println!("sent {}", message.id),
+    ///     Err(err) => match err.retry_after() {
+    ///         Some(retry_after) => tokio::time::sleep(retry_after).await,
+    ///         None => return Err(err),
+    ///     },
+    /// }
+    /// ```
+    pub async fn send_and_confirm(
+        &self,
+        chat_id: i64,
+        message_thread_id: i64,
+        reply_to: Option<InputMessageReplyTo>,
+        options: Option<messageSendOptions>,
+        reply_markup: Option<ReplyMarkup>,
+        input_message_content: InputMessageContent,
+    ) -> Result<message, SendError> {
+        // Subscribe before sending so that no confirmation is missed
+        let updates = self.confirmations();
+        let Message::message(message) = self
+            .sendMessage(
+                chat_id,
+                message_thread_id,
+                reply_to,
+                options,
+                reply_markup,
+                input_message_content,
+            )
+            .await?;
+        let mut messages = self.confirm(updates, vec![message]).await?;
+        Result::Ok(messages.remove(0))
+    }
+
+    /// # Send Album And Confirm
+    /// [Driver::send_and_confirm] for `sendMessageAlbum`: waits until every message of the album
+    /// has been confirmed.
+    ///
+    /// *Note*: if any message of the album failed, the first failure is returned once all of them
+    /// have been confirmed.
+    pub async fn send_album_and_confirm(
+        &self,
+        chat_id: i64,
+        message_thread_id: i64,
+        reply_to: Option<InputMessageReplyTo>,
+        options: Option<messageSendOptions>,
+        input_message_contents: Vec<InputMessageContent>,
+    ) -> Result<Vec<message>, SendError> {
+        let updates = self.confirmations();
+        let messages = self
+            .sendMessageAlbum(
+                chat_id,
+                message_thread_id,
+                reply_to,
+                options,
+                input_message_contents,
+            )
+            .await?;
+        self.confirm(updates, Self::messages(messages)).await
+    }
+
+    /// # Send Inline Query Result And Confirm
+    /// [Driver::send_and_confirm] for `sendInlineQueryResultMessage`
+    #[allow(clippy::too_many_arguments)]
+    pub async fn send_inline_query_result_and_confirm(
+        &self,
+        chat_id: i64,
+        message_thread_id: i64,
+        reply_to: Option<InputMessageReplyTo>,
+        options: Option<messageSendOptions>,
+        query_id: i64,
+        result_id: String,
+        hide_via_bot: bool,
+    ) -> Result<message, SendError> {
+        let updates = self.confirmations();
+        let Message::message(message) = self
+            .sendInlineQueryResultMessage(
+                chat_id,
+                message_thread_id,
+                reply_to,
+                options,
+                query_id,
+                result_id,
+                hide_via_bot,
+            )
+            .await?;
+        let mut messages = self.confirm(updates, vec![message]).await?;
+        Result::Ok(messages.remove(0))
+    }
+
+    /// # Forward And Confirm
+    /// [Driver::send_and_confirm] for `forwardMessages`: waits until every forwarded message has
+    /// been confirmed. Messages that could not be forwarded at all (`null` in the response of
+    /// TDLib) are left out.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn forward_and_confirm(
+        &self,
+        chat_id: i64,
+        message_thread_id: i64,
+        from_chat_id: i64,
+        message_ids: Vec<i64>,
+        options: Option<messageSendOptions>,
+        send_copy: bool,
+        remove_caption: bool,
+    ) -> Result<Vec<message>, SendError> {
+        let updates = self.confirmations();
+        // NOTE: sent raw, because the generated `messages` cannot hold the `null` entries
+        let payload = serde_json::json!({
+            "@type": "forwardMessages", "chat_id": chat_id, "message_thread_id": message_thread_id,
+            "from_chat_id": from_chat_id, "message_ids": message_ids, "options": options,
+            "send_copy": send_copy, "remove_caption": remove_caption,
+        });
+        let messages = self.send::<_, Sent>(payload).await?;
+        self.confirm(updates, messages.sent()).await
+    }
+
+    /// # Resend And Confirm
+    /// [Driver::send_and_confirm] for `resendMessages`, e.g. after waiting for
+    /// [SendError::retry_after]. Messages that could not be re-sent are left out.
+    pub async fn resend_and_confirm(
+        &self,
+        chat_id: i64,
+        message_ids: Vec<i64>,
+        quote: Option<inputTextQuote>,
+    ) -> Result<Vec<message>, SendError> {
+        let updates = self.confirmations();
+        let payload = serde_json::json!({
+            "@type": "resendMessages", "chat_id": chat_id, "message_ids": message_ids,
+            "quote": quote,
+        });
+        let messages = self.send::<_, Sent>(payload).await?;
+        self.confirm(updates, messages.sent()).await
+    }
+
+    /// Subscribes to the updates that confirm sent messages
+    fn confirmations(&self) -> Subscription {
+        self.subscribe_to(["updateMessageSendSucceeded", "updateMessageSendFailed"])
+    }
+
+    /// Messages of a `messages` response
+    fn messages(messages: Messages) -> Vec<message> {
+        let Messages::messages(messages) = messages;
+        messages.messages.unwrap_or_default()
+    }
+
+    /// Waits until every message of `messages` that is still being sent has been confirmed, and
+    /// replaces it with the message as it was sent
+    async fn confirm(
+        &self,
+        mut updates: Subscription,
+        mut messages: Vec<message>,
+    ) -> Result<Vec<message>, SendError> {
+        let mut failure = None;
+
+        // Temporary (chat id, message id) of the messages still being sent
+        let mut pending = HashMap::new();
+        for (index, message) in messages.iter().enumerate() {
+            match &message.sending_state {
+                Some(MessageSendingState::messageSendingStatePending(_)) => {
+                    pending.insert((message.chat_id, message.id), index);
+                }
+                Some(MessageSendingState::messageSendingStateFailed(state)) => {
+                    failure.get_or_insert_with(|| {
+                        SendError::failed(message.clone(), state.error.clone())
+                    });
+                }
+                None => (),
+            }
+        }
+
+        let mut missed = 0;
+        while !pending.is_empty() {
+            match updates.recv().await.as_deref() {
+                Some(Update::updateMessageSendSucceeded(update)) => {
+                    let key = (update.message.chat_id, update.old_message_id);
+                    if let Some(index) = pending.remove(&key) {
+                        messages[index] = update.message.clone();
+                    }
+                }
+                Some(Update::updateMessageSendFailed(update)) => {
+                    let key = (update.message.chat_id, update.old_message_id);
+                    if let Some(index) = pending.remove(&key) {
+                        messages[index] = update.message.clone();
+                        failure.get_or_insert_with(|| {
+                            SendError::failed(update.message.clone(), update.error.clone())
+                        });
+                    }
+                }
+                Some(_) => (),
+                None => return Err(SendError::Closed),
+            }
+
+            // NOTE: updates were skipped; a temporary message that is gone was confirmed among them
+            if updates.missed() != missed {
+                missed = updates.missed();
+                for &(chat_id, message_id) in pending.keys() {
+                    match self.getMessage(chat_id, message_id).await {
+                        Result::Ok(_) => (),
+                        Err(failure) if failure.kind() == ErrorKind::NotFound => {
+                            return Err(SendError::Unconfirmed {
+                                chat_id,
+                                message_id,
+                            })
+                        }
+                        Err(failure) => return Err(failure.into()),
+                    }
+                }
+            }
+        }
+
+        match failure {
+            Some(failure) => Err(failure),
+            None => Result::Ok(messages),
+        }
+    }
+}
+
+#[cfg(test)]
+/// A `message` with `id` in chat 1, sent by the user with `sender_user_id`
+pub(crate) fn message_json(
+    id: i64,
+    sender_user_id: i64,
+    is_outgoing: bool,
+    content: Value,
+    sending_state: Value,
+) -> Value {
+    serde_json::json!({
+        "@type": "message", "id": id, "chat_id": 1, "sending_state": sending_state,
+        "sender_id": { "@type": "messageSenderUser", "user_id": sender_user_id },
+        "is_outgoing": is_outgoing, "is_pinned": false, "is_from_offline": false,
+        "can_be_saved": true, "has_timestamped_media": false, "is_channel_post": false,
+        "is_topic_message": false, "contains_unread_mention": false, "date": 0,
+        "edit_date": 0, "unread_reactions": [], "message_thread_id": 0,
+        "saved_messages_topic_id": 0, "self_destruct_in": 0.0, "auto_delete_in": 0.0,
+        "via_bot_user_id": 0, "sender_business_bot_user_id": 0, "sender_boost_count": 0,
+        "author_signature": "", "media_album_id": 0, "effect_id": 0,
+        "has_sensitive_content": false, "restriction_reason": "", "content": content,
+    })
+}
+
+#[cfg(test)]
+/// `messageText` content of a [message_json] fixture
+pub(crate) fn text_json(text: &str) -> Value {
+    serde_json::json!({ "@type": "messageText",
+        "text": { "@type": "formattedText", "text": text, "entities": [] } })
+}
+
+#[cfg(test)]
+/// An outgoing "hi" of user 1 with `id` and `sending_state`
+fn sent_json(id: i64, sending_state: Value) -> Value {
+    message_json(id, 1, true, text_json("hi"), sending_state)
+}
+
+#[tokio::test]
+/// Sent messages resolve to their confirmed version, and failures carry the retry hint
+async fn send_and_confirm() {
+    let pending = serde_json::json!({ "@type": "messageSendingStatePending", "sending_id": 0 });
+    let error =
+        serde_json::json!({ "@type": "error", "code": 429, "message": "Too Many Requests" });
+    let failed = serde_json::json!({
+        "@type": "messageSendingStateFailed", "error": error, "can_retry": true,
+        "need_another_sender": false, "need_another_reply_quote": false,
+        "need_drop_reply": false, "retry_after": 3.0,
+    });
+
+    let transport = MemoryTransport::new();
+    transport.respond_with({
+        let pending = pending.clone();
+        move |request| {
+            let id = request["message_ids"][0].as_i64().unwrap_or(-1);
+            match request["@type"].as_str()? {
+                "sendMessage" => Some(sent_json(id, pending.clone())),
+                "resendMessages" => Some(serde_json::json!({
+                    "@type": "messages", "total_count": 1,
+                    "messages": [sent_json(id, pending.clone())],
+                })),
+                _ => None,
+            }
+        }
+    });
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+    let content = InputMessageContent::inputMessageText(inputMessageText {
+        text: formattedText {
+            text: "hi".into(),
+            entities: vec![],
+        },
+        link_preview_options: None,
+        clear_draft: false,
+    });
+
+    // Confirmed with its final id
+    let sent = tokio::spawn({
+        let driver = driver.clone();
+        let content = content.clone();
+        async move {
+            driver
+                .send_and_confirm(1, 0, None, None, None, content)
+                .await
+        }
+    });
+    while transport.requests().is_empty() {
+        tokio::task::yield_now().await;
+    }
+    let update = serde_json::json!({
+        "@type": "updateMessageSendSucceeded", "old_message_id": -1,
+        "message": sent_json(100, Value::Null),
+    });
+    transport.push(driver.id, update);
+    assert_eq!(sent.await.unwrap().unwrap().id, 100);
+
+    // Failed with a retry hint
+    let resent = tokio::spawn({
+        let driver = driver.clone();
+        async move { driver.resend_and_confirm(1, vec![-2], None).await }
+    });
+    while transport.requests().len() < 2 {
+        tokio::task::yield_now().await;
+    }
+    let update = serde_json::json!({
+        "@type": "updateMessageSendFailed", "old_message_id": -2, "error": error,
+        "message": sent_json(-2, failed),
+    });
+    transport.push(driver.id, update);
+    let err = resent.await.unwrap().unwrap_err();
+    assert!(matches!(
+        err,
+        SendError::Failed {
+            can_retry: true,
+            ..
+        }
+    ));
+    assert_eq!(err.retry_after(), Some(Duration::from_secs(3)));
+}
+
+#[tokio::test]
+/// Messages that could not be forwarded are left out instead of failing the whole response
+async fn forward_and_confirm() {
+    let pending = serde_json::json!({ "@type": "messageSendingStatePending", "sending_id": 0 });
+
+    let transport = MemoryTransport::new();
+    transport.respond_with(move |request| match request["@type"].as_str()? {
+        "forwardMessages" => Some(serde_json::json!({
+            "@type": "messages", "total_count": 3,
+            "messages": [sent_json(-1, pending.clone()), null, sent_json(-3, pending.clone())],
+        })),
+        _ => None,
+    });
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+
+    let forwarded = tokio::spawn({
+        let driver = driver.clone();
+        async move {
+            driver
+                .forward_and_confirm(1, 0, 2, vec![1, 2, 3], None, false, false)
+                .await
+        }
+    });
+    while transport.requests().is_empty() {
+        tokio::task::yield_now().await;
+    }
+    for (old_message_id, id) in [(-1, 101), (-3, 103)] {
+        let update = serde_json::json!({
+            "@type": "updateMessageSendSucceeded", "old_message_id": old_message_id,
+            "message": sent_json(id, Value::Null),
+        });
+        transport.push(driver.id, update);
+    }
+    let messages = forwarded.await.unwrap().unwrap();
+    let ids = messages
+        .iter()
+        .map(|message| message.id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [101, 103]);
+}
@@ -0,0 +1,632 @@
+use super::query::DEFAULT_ANSWER_TIMEOUT;
+use super::*;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::Duration;
+
+/// Handler of a [Command], boxed so that commands with different handlers fit in one [Dispatcher]
+type Handler<State> =
+    Arc<dyn Fn(CommandContext<State>) -> BoxFuture<'static, Result<(), Failure>> + Send + Sync>;
+
+/// Handler of callback queries with one key, called with the JSON payload of the button
+type CallbackHandler<State> = Arc<
+    dyn Fn(
+            Arc<Driver>,
+            Arc<State>,
+            updateNewCallbackQuery,
+            String,
+        ) -> BoxFuture<'static, Result<CallbackAnswer, Failure>>
+        + Send
+        + Sync,
+>;
+
+/// Handler of inline queries
+type InlineHandler<State> = Arc<
+    dyn Fn(InlineContext<State>) -> BoxFuture<'static, Result<InlineAnswer, Failure>> + Send + Sync,
+>;
+
+/// Kind of chat a command was sent in, for [Command::chats]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChatKind {
+    Private,
+    Group,
+    Supergroup,
+    Channel,
+    Secret,
+}
+
+impl ChatKind {
+    fn new(chat_type: &ChatType) -> Self {
+        match chat_type {
+            ChatType::chatTypePrivate(_) => Self::Private,
+            ChatType::chatTypeBasicGroup(_) => Self::Group,
+            ChatType::chatTypeSupergroup(supergroup) if supergroup.is_channel => Self::Channel,
+            ChatType::chatTypeSupergroup(_) => Self::Supergroup,
+            ChatType::chatTypeSecret(_) => Self::Secret,
+        }
+    }
+}
+
+/// # Command
+/// A `/command` of a [Dispatcher] and the handler it is routed to.
+/// - [Command::chats]: only accept the command in some kinds of chat
+/// - [Command::senders]: only accept the command from some users or chats
+/// - [Command::hidden]: leave the command out of `setCommands`
+pub struct Command<State> {
+    name: String,
+    description: String,
+    chats: Option<HashSet<ChatKind>>,
+    senders: Option<HashSet<i64>>,
+    hidden: bool,
+    handler: Handler<State>,
+}
+
+impl<State> Command<State> {
+    /// # New
+    /// Arguments:
+    /// - `name`: name of the command, without the leading `/`
+    /// - `description`: description of the command, as registered with `setCommands`
+    /// - `handler`: async function that handles the command
+    pub fn new<Name, Description, Handle, Fut>(
+        name: Name,
+        description: Description,
+        handler: Handle,
+    ) -> Self
+    where
+        Name: Into<String>,
+        Description: Into<String>,
+        Handle: Fn(CommandContext<State>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Failure>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            chats: None,
+            senders: None,
+            hidden: false,
+            handler: Arc::new(move |context| handler(context).boxed()),
+        }
+    }
+
+    /// Only accepts the command in the given kinds of chat
+    pub fn chats(mut self, chats: impl IntoIterator<Item = ChatKind>) -> Self {
+        self.chats = Some(chats.into_iter().collect());
+        self
+    }
+
+    /// Only accepts the command from the given users or chats
+    pub fn senders(mut self, senders: impl IntoIterator<Item = i64>) -> Self {
+        self.senders = Some(senders.into_iter().collect());
+        self
+    }
+
+    /// Leaves the command out of `setCommands`, e.g. for admin commands
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+}
+
+/// # Command Context
+/// What a [Command] handler is called with
+pub struct CommandContext<State> {
+    /// The driver the command was received through
+    pub driver: Arc<Driver>,
+
+    /// Shared state of the [Dispatcher]
+    pub state: Arc<State>,
+
+    /// The message that contains the command
+    pub message: message,
+
+    /// Name of the command, without the leading `/` and the bot username
+    pub command: String,
+
+    /// Text after the command, trimmed
+    pub text: String,
+
+    /// `text` split at whitespace
+    pub args: Vec<String>,
+}
+
+impl<State> CommandContext<State> {
+    /// Id of the chat the command was sent in
+    pub fn chat_id(&self) -> i64 {
+        self.message.chat_id
+    }
+
+    /// Id of the user or chat that sent the command
+    pub fn sender_id(&self) -> i64 {
+        sender_id(&self.message.sender_id)
+    }
+
+    /// Argument at `index` parsed as `T`; [None] if it is missing or does not parse
+    pub fn arg<T: std::str::FromStr>(&self, index: usize) -> Option<T> {
+        self.args.get(index)?.parse().ok()
+    }
+}
+
+/// # Dispatcher
+/// Routes `/command`s of new text messages to async handlers, built on [Driver::subscribe_to].
+/// `/command@username` is accepted only if `username` is the bot's own, so that commands meant
+/// for other bots in a group are left alone. Every handler runs in its own task and gets a
+/// [CommandContext] with the shared state of the dispatcher.
+///
+/// Callback queries of buttons made with [callback_button] are routed by their key to the
+/// handlers of [Dispatcher::callback], and inline queries to the handler of
+/// [Dispatcher::inline_query]. Their handlers return the answer, which the dispatcher sends.
+///
+/// *Note*: on [Dispatcher::run], the commands that are not [Command::hidden] are registered with
+/// `setCommands` unless [Dispatcher::register_commands] turned that off. Failures of handlers are
+/// logged. A query whose handler fails or takes longer than [Dispatcher::answer_timeout] is
+/// answered with an empty response, so that the client does not wait in vain.
+/// # Example
+/// ```ignore
+/// async fn roll(context: CommandContext<Dice>) -> Result<(), Failure> {
+///     let sides = context.arg(0).unwrap_or(6);
+///     let roll = context.state.roll(sides);
+///     ..
+/// }
+///
+/// Dispatcher::new(Dice::default())
+///     .command(Command::new("roll", "Roll a die", roll))
+///     .command(Command::new("ban", "Ban a user", ban).chats([ChatKind::Supergroup]).hidden())
+///     .run(driver)
+///     .await?;
+/// ```
+pub struct Dispatcher<State> {
+    state: Arc<State>,
+    commands: HashMap<String, Command<State>>,
+    register: bool,
+    callbacks: HashMap<String, CallbackHandler<State>>,
+    answer_unknown: bool,
+    inline: Option<InlineHandler<State>>,
+    answer_timeout: Duration,
+}
+
+impl<State: Send + Sync + 'static> Dispatcher<State> {
+    /// Creates a dispatcher without commands that hands `state` to every handler
+    pub fn new(state: State) -> Self {
+        Self {
+            state: Arc::new(state),
+            commands: HashMap::new(),
+            register: true,
+            callbacks: HashMap::new(),
+            answer_unknown: false,
+            inline: None,
+            answer_timeout: DEFAULT_ANSWER_TIMEOUT,
+        }
+    }
+
+    /// Adds `command`, replacing any command with the same name
+    pub fn command(mut self, command: Command<State>) -> Self {
+        self.commands.insert(command.name.to_lowercase(), command);
+        self
+    }
+
+    /// Whether to register the commands with `setCommands` on [Dispatcher::run]; on by default
+    pub fn register_commands(mut self, register: bool) -> Self {
+        self.register = register;
+        self
+    }
+
+    /// # Callback
+    /// Routes callback queries of buttons made with [callback_button] for `key` to `handler`,
+    /// replacing any handler of the same key.
+    ///
+    /// *Note*: a query whose payload does not decode as `Payload` is answered with an empty
+    /// response. A `key` that contains a `:` is never matched, as [callback_data] rejects it.
+    pub fn callback<Payload, Handle, Fut>(mut self, key: &str, handler: Handle) -> Self
+    where
+        Payload: serde::de::DeserializeOwned + Send + 'static,
+        Handle: Fn(CallbackContext<State, Payload>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<CallbackAnswer, Failure>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let handler: CallbackHandler<State> = Arc::new(move |driver, state, query, payload| {
+            let handler = handler.clone();
+            async move {
+                let payload = decode_payload(&payload)?;
+                handler(CallbackContext {
+                    driver,
+                    state,
+                    query,
+                    payload,
+                })
+                .await
+            }
+            .boxed()
+        });
+        self.callbacks.insert(key.to_string(), handler);
+        self
+    }
+
+    /// # Answer Unknown Callbacks
+    /// Whether to answer callback queries without a handler for their key with an empty response,
+    /// so that their button stops loading; off by default, which leaves them to other clients.
+    ///
+    /// *Note*: only takes effect if some [Dispatcher::callback] is registered, as the dispatcher
+    /// does not listen for callback queries otherwise.
+    pub fn answer_unknown_callbacks(mut self, answer_unknown: bool) -> Self {
+        self.answer_unknown = answer_unknown;
+        self
+    }
+
+    /// Routes inline queries to `handler`
+    pub fn inline_query<Handle, Fut>(mut self, handler: Handle) -> Self
+    where
+        Handle: Fn(InlineContext<State>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<InlineAnswer, Failure>> + Send + 'static,
+    {
+        self.inline = Some(Arc::new(move |context| handler(context).boxed()));
+        self
+    }
+
+    /// # Answer Timeout
+    /// How long query handlers may take before the query is answered with an empty response.
+    ///
+    /// *Note*: a handler that times out is not cancelled; it keeps running in its own task, but
+    /// its answer is dropped.
+    pub fn answer_timeout(mut self, answer_timeout: Duration) -> Self {
+        self.answer_timeout = answer_timeout;
+        self
+    }
+
+    /// # Run
+    /// Registers the commands and dispatches commands and queries until the client is closed,
+    /// either by [Driver::shutdown] or by `authorizationStateClosed`
+    pub async fn run(self, driver: Arc<Driver>) -> Result<(), Failure> {
+        // Subscribe before registering so that no command is missed
+        // NOTE: queries without a handler are left to other clients of the same bot
+        let mut kinds = vec!["updateNewMessage", "updateAuthorizationState"];
+        if !self.callbacks.is_empty() {
+            kinds.push("updateNewCallbackQuery");
+        }
+        if self.inline.is_some() {
+            kinds.push("updateNewInlineQuery");
+        }
+        let mut updates = driver.subscribe_to(kinds);
+
+        // NOTE: a dispatcher of queries alone needs neither the username nor the commands
+        let mut usernames = Vec::new();
+        if !self.commands.is_empty() {
+            let User::user(me) = driver.getMe().await?;
+            usernames = me
+                .usernames
+                .map(|usernames| usernames.active_usernames)
+                .unwrap_or_default();
+        }
+        if self.register && !self.commands.is_empty() {
+            let mut commands: Vec<_> = self
+                .commands
+                .values()
+                .filter(|command| !command.hidden)
+                .map(|command| botCommand {
+                    command: command.name.clone(),
+                    description: command.description.clone(),
+                })
+                .collect();
+            commands.sort_by(|a, b| a.command.cmp(&b.command));
+            driver.setCommands(None, String::new(), commands).await?;
+        }
+
+        // NOTE: the dispatcher holds on to the driver, so its updates would never run out
+        loop {
+            let update = tokio::select! {
+                update = updates.recv() => update,
+                _ = driver.closed() => None,
+            };
+            let Some(update) = update else {
+                break;
+            };
+            match update.as_ref() {
+                Update::updateNewMessage(updateNewMessage { message }) => {
+                    self.dispatch_command(&driver, message, &usernames)
+                }
+                Update::updateNewCallbackQuery(query) => self.dispatch_callback(&driver, query),
+                Update::updateNewInlineQuery(query) => self.dispatch_inline_query(&driver, query),
+                Update::updateAuthorizationState(updateAuthorizationState {
+                    authorization_state: AuthorizationState::authorizationStateClosed,
+                }) => break,
+                _ => (),
+            }
+        }
+
+        Result::Ok(())
+    }
+
+    /// Hands `message` to the handler of its command, if any
+    fn dispatch_command(&self, driver: &Arc<Driver>, message: &message, usernames: &[String]) {
+        if message.is_outgoing {
+            return;
+        }
+        let MessageContent::messageText(content) = &message.content else {
+            return;
+        };
+        let Some((name, text)) = parse(&content.text.text, usernames) else {
+            return;
+        };
+        let Some(command) = self.commands.get(&name) else {
+            return;
+        };
+        if command
+            .senders
+            .as_ref()
+            .is_some_and(|senders| !senders.contains(&sender_id(&message.sender_id)))
+        {
+            return;
+        }
+
+        let context = CommandContext {
+            driver: driver.clone(),
+            state: self.state.clone(),
+            message: message.clone(),
+            args: text.split_whitespace().map(String::from).collect(),
+            command: name,
+            text,
+        };
+        let chats = command.chats.clone();
+        let handler = command.handler.clone();
+        tokio::spawn(async move {
+            if let Some(chats) = chats {
+                match chat_kind(&context.driver, context.chat_id()).await {
+                    Result::Ok(kind) if chats.contains(&kind) => (),
+                    Result::Ok(_) => return,
+                    Err(failure) => {
+                        tracing::warn!(command = context.command, %failure, "chat lookup failed");
+                        return;
+                    }
+                }
+            }
+            let command = context.command.clone();
+            if let Err(failure) = handler(context).await {
+                tracing::warn!(command, %failure, "command failed");
+            }
+        });
+    }
+
+    /// Answers `query` with what the handler of its key returns
+    fn dispatch_callback(&self, driver: &Arc<Driver>, query: &updateNewCallbackQuery) {
+        let handling = match &query.payload {
+            CallbackQueryPayload::callbackQueryPayloadData(payload) => {
+                decode_callback_data(&payload.data).and_then(|(key, payload)| {
+                    let handler = self.callbacks.get(&key)?;
+                    let state = self.state.clone();
+                    Some(handler(driver.clone(), state, query.clone(), payload))
+                })
+            }
+            _ => None,
+        };
+
+        if handling.is_none() && !self.answer_unknown {
+            return;
+        }
+
+        let driver = driver.clone();
+        let query_id = query.id;
+        let timeout = self.answer_timeout;
+        tokio::spawn(async move {
+            let answer = match handling {
+                Some(handling) => answer(timeout, "callback", handling).await,
+                // NOTE: answered anyway, so that the button stops loading
+                None => CallbackAnswer::default(),
+            };
+            if let Err(failure) = answer.send(&driver, query_id).await {
+                tracing::warn!(query_id, %failure, "failed to answer callback query");
+            }
+        });
+    }
+
+    /// Answers `query` with what the inline query handler returns
+    fn dispatch_inline_query(&self, driver: &Arc<Driver>, query: &updateNewInlineQuery) {
+        let Some(handler) = &self.inline else {
+            return;
+        };
+        let handling = handler(InlineContext {
+            driver: driver.clone(),
+            state: self.state.clone(),
+            query: query.clone(),
+        });
+
+        let driver = driver.clone();
+        let query_id = query.id;
+        let timeout = self.answer_timeout;
+        tokio::spawn(async move {
+            let answer = answer(timeout, "inline", handling).await;
+            if let Err(failure) = answer.send(&driver, query_id).await {
+                tracing::warn!(query_id, %failure, "failed to answer inline query");
+            }
+        });
+    }
+}
+
+/// Waits for the answer of a query handler; the default, empty answer if the handler fails or
+/// does not answer within `timeout`
+async fn answer<Answer: Default + Send + 'static>(
+    timeout: Duration,
+    query: &'static str,
+    handling: BoxFuture<'static, Result<Answer, Failure>>,
+) -> Answer {
+    // NOTE: spawned so that a handler that times out still runs to completion
+    let mut handling = tokio::spawn(handling);
+    match tokio::time::timeout(timeout, &mut handling).await {
+        Result::Ok(Result::Ok(Result::Ok(answer))) => answer,
+        Result::Ok(Result::Ok(Err(failure))) => {
+            tracing::warn!(query, %failure, "query handler failed");
+            Answer::default()
+        }
+        Result::Ok(Err(err)) => {
+            tracing::warn!(query, %err, "query handler panicked");
+            Answer::default()
+        }
+        Err(_) => {
+            tracing::warn!(query, ?timeout, "query handler timed out");
+            Answer::default()
+        }
+    }
+}
+
+/// Splits `/command@username arguments` into the lowercase command and the trimmed arguments;
+/// [None] if `text` is no command or one addressed to another bot
+fn parse(text: &str, usernames: &[String]) -> Option<(String, String)> {
+    let text = text.strip_prefix('/')?;
+    let (command, text) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let command = match command.split_once('@') {
+        Some((command, username))
+            if usernames
+                .iter()
+                .any(|ours| ours.eq_ignore_ascii_case(username)) =>
+        {
+            command
+        }
+        Some(_) => return None,
+        None => command,
+    };
+    (!command.is_empty()).then(|| (command.to_lowercase(), text.trim().to_string()))
+}
+
+/// Id of the user or chat of `sender`
+fn sender_id(sender: &MessageSender) -> i64 {
+    match sender {
+        MessageSender::messageSenderUser(sender) => sender.user_id,
+        MessageSender::messageSenderChat(sender) => sender.chat_id,
+    }
+}
+
+/// Kind of the chat with `chat_id`, from the [State] if the driver keeps one
+async fn chat_kind(driver: &Driver, chat_id: i64) -> Result<ChatKind, Failure> {
+    if let Some(chat) = driver.state().and_then(|state| state.chat(chat_id)) {
+        return Result::Ok(ChatKind::new(&chat.r#type));
+    }
+    let Chat::chat(chat) = driver.getChat(chat_id).await?;
+    Result::Ok(ChatKind::new(&chat.r#type))
+}
+
+#[tokio::test]
+/// Running stops once the driver shuts down or the client is closed
+async fn dispatcher_stops() {
+    let transport = MemoryTransport::new();
+    transport.respond_with(|request| match request["@type"].as_str()? {
+        "getAuthorizationState" => Some(serde_json::json!({ "@type": "authorizationStateClosed" })),
+        _ => None,
+    });
+    let manager = ClientManager::new(transport.clone());
+    let timeout = Duration::from_secs(5);
+
+    // Shut down
+    let driver = Arc::new(manager.client(None));
+    let running = tokio::spawn(Dispatcher::new(()).run(driver.clone()));
+    tokio::task::yield_now().await;
+    driver.shutdown(timeout).await.unwrap();
+    let run = tokio::time::timeout(timeout, running).await.unwrap();
+    assert!(run.unwrap().is_ok());
+
+    // Closed
+    let driver = Arc::new(manager.client(None));
+    let running = tokio::spawn(Dispatcher::new(()).run(driver.clone()));
+    tokio::task::yield_now().await;
+    let update = serde_json::json!({
+        "@type": "updateAuthorizationState",
+        "authorization_state": { "@type": "authorizationStateClosed" },
+    });
+    transport.push(driver.id, update);
+    let run = tokio::time::timeout(timeout, running).await.unwrap();
+    assert!(run.unwrap().is_ok());
+}
+
+#[tokio::test]
+/// Commands are registered, parsed and routed, and commands for other bots are ignored
+async fn dispatcher() {
+    use crate::confirm::message_json;
+    use crate::confirm::text_json;
+    use std::sync::atomic::AtomicI64;
+
+    let user = serde_json::json!({
+        "@type": "user", "id": 1, "first_name": "Bot", "last_name": "",
+        "usernames": { "@type": "usernames", "active_usernames": ["MyBot"],
+            "disabled_usernames": [], "editable_username": "MyBot" },
+        "phone_number": "", "status": { "@type": "userStatusEmpty" }, "accent_color_id": 0,
+        "background_custom_emoji_id": 0, "profile_accent_color_id": 0,
+        "profile_background_custom_emoji_id": 0, "is_contact": false,
+        "is_mutual_contact": false, "is_close_friend": false, "is_verified": false,
+        "is_premium": false, "is_support": false, "restriction_reason": "", "is_scam": false,
+        "is_fake": false, "has_active_stories": false, "has_unread_active_stories": false,
+        "restricts_new_chats": false, "have_access": true, "type": { "@type": "userTypeRegular" },
+        "language_code": "", "added_to_attachment_menu": false,
+    });
+    let message = |sender: i64, text: &str| {
+        let message = message_json(1, sender, false, text_json(text), Value::Null);
+        serde_json::json!({ "@type": "updateNewMessage", "message": message })
+    };
+
+    let transport = MemoryTransport::new();
+    transport.respond_with(move |request| match request["@type"].as_str()? {
+        "getMe" => Some(user.clone()),
+        "setCommands" => Some(serde_json::json!({ "@type": "ok" })),
+        _ => None,
+    });
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+
+    // Adds up the arguments of /add
+    let dispatcher = Dispatcher::new(AtomicI64::new(0))
+        .command(Command::new(
+            "add",
+            "Add a number",
+            |context: CommandContext<AtomicI64>| async move {
+                let number: i64 = context.arg(0).unwrap_or_default();
+                context.state.fetch_add(number, Ordering::SeqCst);
+                Result::Ok(())
+            },
+        ))
+        .command(
+            Command::new(
+                "reset",
+                "Reset the sum",
+                |context: CommandContext<AtomicI64>| async move {
+                    context.state.store(0, Ordering::SeqCst);
+                    Result::Ok(())
+                },
+            )
+            .senders([42])
+            .hidden(),
+        );
+    let sum = dispatcher.state.clone();
+    tokio::spawn(dispatcher.run(driver.clone()));
+    while transport.requests().len() < 2 {
+        tokio::task::yield_now().await;
+    }
+    let registered = &transport.requests()[1].1;
+    assert_eq!(registered["@type"], "setCommands");
+    assert_eq!(registered["commands"].as_array().unwrap().len(), 1);
+
+    for (sender, text) in [
+        (7, "/add 2"),
+        (7, "/ADD@mybot 3 ignored"),
+        (7, "/add@otherbot 100"),
+        (7, "add 100"),
+        (7, "/reset"),
+        (42, "/unknown"),
+        (42, "/add 5"),
+    ] {
+        transport.push(driver.id, message(sender, text));
+    }
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while sum.load(Ordering::SeqCst) != 10 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+
+    transport.push(driver.id, message(42, "/reset"));
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while sum.load(Ordering::SeqCst) != 0 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,327 @@
+use super::*;
+use crate::file_watch::*;
+use futures::Stream;
+use tokio::sync::Semaphore;
+
+/// Number of files a [Downloads] manager downloads at once when nothing else has been configured
+pub const DEFAULT_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// Priority of downloads issued by a [Downloads] manager when nothing else has been configured
+pub const DEFAULT_DOWNLOAD_PRIORITY: i32 = 1;
+
+/// Progress of a [Download]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Bytes downloaded so far
+    pub downloaded: i64,
+
+    /// Expected size of the file in bytes; 0 if unknown
+    pub expected: i64,
+}
+
+impl DownloadProgress {
+    fn new(file: &file) -> Self {
+        Self {
+            downloaded: file.local.downloaded_size,
+            expected: file.expected_size.max(file.size),
+        }
+    }
+
+    /// Share of the file downloaded so far, between 0 and 1; [None] if the size is unknown
+    pub fn fraction(&self) -> Option<f64> {
+        (self.expected > 0).then(|| (self.downloaded as f64 / self.expected as f64).min(1.0))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Errors of a [Download]
+pub enum DownloadError {
+    /// The download was cancelled with [Download::cancel]
+    Cancelled,
+
+    /// TDLib stopped downloading the file before it was complete, e.g. because it was cancelled
+    /// elsewhere
+    Interrupted,
+
+    /// The [Driver] went away before the download completed
+    Closed,
+
+    /// A request failed, e.g. `downloadFile` for a file id that does not exist
+    Failure(Arc<Failure>),
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "download cancelled"),
+            Self::Interrupted => write!(f, "download stopped before it was complete"),
+            Self::Closed => write!(f, "client is closed"),
+            Self::Failure(failure) => write!(f, "{failure}"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Failure(failure) => Some(failure.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Failure> for DownloadError {
+    fn from(value: Failure) -> Self {
+        Self::Failure(Arc::new(value))
+    }
+}
+
+/// `updateFile`s of a download, as followed by a [FileWatch]
+struct Downloading;
+
+impl Transfer for Downloading {
+    type Progress = DownloadProgress;
+    type Error = DownloadError;
+
+    const CANCELLED: DownloadError = DownloadError::Cancelled;
+    const INTERRUPTED: DownloadError = DownloadError::Interrupted;
+    const CLOSED: DownloadError = DownloadError::Closed;
+
+    fn progress(file: &file) -> DownloadProgress {
+        DownloadProgress::new(file)
+    }
+
+    fn is_completed(file: &file) -> bool {
+        file.local.is_downloading_completed
+    }
+
+    fn is_active(file: &file) -> bool {
+        file.local.is_downloading_active
+    }
+
+    async fn cancel(&self, driver: &Driver, file_id: i32) -> Result<(), Failure> {
+        driver.cancelDownloadFile(file_id, false).await.map(|_| ())
+    }
+}
+
+/// # Downloads
+/// Download manager of a [Driver]. `downloadFile` only starts a download; its progress then
+/// trickles in as `updateFile`. [Downloads::download] issues the download and follows those
+/// updates, so that a [Download] can report its [DownloadProgress] and resolve to the local path
+/// of the file:
+/// - Concurrent downloads of the same file share one [Download].
+/// - At most [Downloads::max_concurrent] files are downloaded at once; the rest queue up.
+///
+/// Clones share their downloads and their limit.
+/// # Example
+/// ```ignore
+/// let downloads = Downloads::new(driver.clone()).max_concurrent(2);
+///
+/// let download = downloads.download(file_id);
+/// let mut progress = download.progress();
+/// tokio::spawn(async move {
+///     while let Some(DownloadProgress { downloaded, expected }) = progress.next().await {
+///         println!("{downloaded}/{expected}");
+///     }
+/// });
+/// let path = download.wait().await?;
+/// ```
+#[derive(Clone)]
+pub struct Downloads {
+    driver: Arc<Driver>,
+    priority: i32,
+    slots: Arc<Semaphore>,
+    jobs: Arc<Mutex<HashMap<i32, Arc<FileWatch<Downloading>>>>>,
+}
+
+impl Downloads {
+    /// Manages the downloads of `driver`, [DEFAULT_CONCURRENT_DOWNLOADS] at a time
+    pub fn new(driver: Arc<Driver>) -> Self {
+        Self {
+            driver,
+            priority: DEFAULT_DOWNLOAD_PRIORITY,
+            slots: Arc::new(Semaphore::new(DEFAULT_CONCURRENT_DOWNLOADS)),
+            jobs: Arc::default(),
+        }
+    }
+
+    /// Number of files downloaded at once
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.slots = Arc::new(Semaphore::new(max_concurrent.max(1)));
+        self
+    }
+
+    /// Priority of the downloads, from 1 to 32; see [Api::downloadFile]
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority.clamp(1, 32);
+        self
+    }
+
+    /// Locks the downloads in progress
+    fn jobs(&self) -> std::sync::MutexGuard<'_, HashMap<i32, Arc<FileWatch<Downloading>>>> {
+        // NOTE: safe to unwrap because poison cleared
+        while self.jobs.is_poisoned() {
+            self.jobs.clear_poison();
+        }
+        self.jobs.lock().unwrap()
+    }
+
+    /// # Download
+    /// Downloads the file with `file_id`, or joins the download of it that is already in progress.
+    ///
+    /// *Note*: has to be called from within a [tokio] runtime. Dropping the [Download] does not
+    /// stop the download; use [Download::cancel].
+    pub fn download(&self, file_id: i32) -> Download {
+        let mut jobs = self.jobs();
+        if let Some(job) = jobs.get(&file_id) {
+            return Download {
+                file_id,
+                job: job.clone(),
+            };
+        }
+
+        let (watch, mut tracker) = FileWatch::new(Status::Queued);
+        let job = Arc::new(watch);
+        jobs.insert(file_id, job.clone());
+
+        let downloads = self.clone();
+        tokio::spawn(async move {
+            let result = downloads.run(file_id, &mut tracker).await;
+            downloads.jobs().remove(&file_id);
+            if let Err(err) = result {
+                tracker.fail(err);
+            }
+        });
+
+        Download { file_id, job }
+    }
+
+    /// Waits for a slot, then downloads the file and follows its `updateFile`s until it is
+    /// complete
+    async fn run(
+        &self,
+        file_id: i32,
+        tracker: &mut Tracker<Downloading>,
+    ) -> Result<(), DownloadError> {
+        let driver = &self.driver;
+
+        let _slot = tokio::select! {
+            slot = self.slots.clone().acquire_owned() => slot.map_err(|_| DownloadError::Closed)?,
+            _ = tracker.cancelled() => return Err(DownloadError::Cancelled),
+        };
+
+        // Subscribe before downloading so that no update is missed
+        let updates = driver.subscribe_to(["updateFile"]);
+        let File::file(file) = driver
+            .downloadFile(file_id, self.priority, 0, 0, false)
+            .await?;
+        tracker.follow(&Downloading, driver, file, updates).await
+    }
+}
+
+/// # Download
+/// Handle to a download started by [Downloads::download]
+pub struct Download {
+    file_id: i32,
+    job: Arc<FileWatch<Downloading>>,
+}
+
+impl Download {
+    /// Id of the file being downloaded
+    pub fn file_id(&self) -> i32 {
+        self.file_id
+    }
+
+    /// Latest progress of the download
+    pub fn current(&self) -> DownloadProgress {
+        self.job.current()
+    }
+
+    /// # Progress
+    /// [Stream] of the progress of the download, starting with the latest. Intermediate progress
+    /// is skipped if the stream is not polled in time. The stream ends once the download has
+    /// completed or failed.
+    pub fn progress(&self) -> impl Stream<Item = DownloadProgress> + Send + Unpin + 'static {
+        self.job.progress()
+    }
+
+    /// # Wait
+    /// Waits for the download to complete and returns the local path of the file
+    pub async fn wait(&self) -> Result<String, DownloadError> {
+        let file = self.job.wait().await?;
+        Result::Ok(file.local.path)
+    }
+
+    /// # Cancel
+    /// Cancels the download with `cancelDownloadFile`, for every handle to it. [Download::wait]
+    /// then fails with [DownloadError::Cancelled].
+    pub fn cancel(&self) {
+        self.job.cancel();
+    }
+}
+
+#[tokio::test]
+/// Downloads report progress, resolve to their path, share a file and queue up beyond the limit
+async fn downloads() {
+    use futures::StreamExt;
+
+    let file = |id: i32, downloaded: i64, active: bool| {
+        let local = Transferred {
+            bytes: downloaded,
+            active,
+        };
+        file_json(id, 100, local, Transferred::idle(100))
+    };
+
+    let transport = MemoryTransport::new();
+    transport.respond_with(move |request| match request["@type"].as_str() {
+        Some("downloadFile") => Some(file(request["file_id"].as_i64()? as i32, 0, true)),
+        Some("cancelDownloadFile") => Some(serde_json::json!({ "@type": "ok" })),
+        _ => None,
+    });
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+    let downloads = Downloads::new(driver.clone()).max_concurrent(1);
+    let requested = |method: &str| {
+        transport
+            .requests()
+            .iter()
+            .filter(|(_, request)| request["@type"] == method)
+            .map(|(_, request)| request["file_id"].as_i64().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let until_requested = |method: &'static str, count: usize| {
+        let requested = &requested;
+        async move {
+            while requested(method).len() < count {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        }
+    };
+
+    // The same file is downloaded once; the second file waits for the first
+    let first = downloads.download(1);
+    let again = downloads.download(1);
+    let second = downloads.download(2);
+    let mut progress = first.progress();
+    until_requested("downloadFile", 1).await;
+
+    for downloaded in [50, 100] {
+        let update =
+            serde_json::json!({ "@type": "updateFile", "file": file(1, downloaded, true) });
+        transport.push(driver.id, update);
+    }
+    assert_eq!(again.wait().await.unwrap(), "/files/1");
+    let progress: Vec<_> = (&mut progress)
+        .map(|progress| progress.downloaded)
+        .collect()
+        .await;
+    assert_eq!(progress.last(), Some(&100));
+    assert_eq!(first.current().fraction(), Some(1.0));
+
+    // Cancelled while running
+    until_requested("downloadFile", 2).await;
+    assert_eq!(requested("downloadFile"), [1, 2]);
+    second.cancel();
+    assert!(matches!(second.wait().await, Err(DownloadError::Cancelled)));
+    assert_eq!(requested("cancelDownloadFile"), [2]);
+}
@@ -0,0 +1,21 @@
+use super::*;
+
+/// # Executor
+/// Calls the TDLib functions that can be called synchronously (see [SyncApi]) through the blocking
+/// `td_execute`. No client or receive loop is involved, so these can be called at any point,
+/// including before the first [Driver] is created.
+/// # Example
+/// ```ignore
+/// let entities = Executor.getTextEntities("@telegram https://telegram.org".into())?;
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Executor;
+
+impl SyncApi for Executor {
+    fn execute<Payload: Serialize, Target: DeserializeOwned>(
+        &self,
+        payload: Payload,
+    ) -> Result<Target, Failure> {
+        transport::execute(&Tdjson, payload)
+    }
+}
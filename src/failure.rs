@@ -0,0 +1,231 @@
+use super::*;
+use std::time::Duration;
+
+#[derive(Debug)]
+/// # Failure
+/// Error of a request: either TDLib answered with an [Error], or the driver could not get an
+/// answer at all. Use [Failure::kind] to match on the cause.
+pub enum Failure {
+    TdLib(Error),
+    Receiver(ReceiverError),
+}
+
+#[derive(Debug)]
+pub enum ReceiverError {
+    Serde(serde_json::Error),
+    Channel(tokio::sync::oneshot::error::RecvError),
+    /// A task with the same id is still pending; only possible once the task counter has wrapped
+    /// around
+    QueueFull,
+    Timeout(tokio::time::error::Elapsed),
+    /// The [Driver] is shutting down and no longer accepts requests
+    ShuttingDown,
+    /// `td_execute` returned no result; the request cannot be executed synchronously
+    Empty,
+    /// A [DriverBuilder] was built without a [ClientManager], which is required without the
+    /// `tdjson` feature
+    NoManager,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// # Error Kind
+/// Cause of a [Failure], classified from the code and message of a TDLib [Error] or from the
+/// [ReceiverError]
+pub enum ErrorKind {
+    /// Too many requests; the request may be repeated after `retry_after`. Parsed from
+    /// `FLOOD_WAIT_X` and `Too Many Requests: retry after X`.
+    FloodWait { retry_after: Duration },
+
+    /// The request is malformed or one of its arguments is invalid (400)
+    BadRequest,
+
+    /// The client is not authorized to make the request (401)
+    Unauthorized,
+
+    /// The requested object does not exist (404)
+    NotFound,
+
+    /// TDLib or the server failed internally (5xx)
+    Internal,
+
+    /// No response arrived in time
+    Timeout,
+
+    /// The request was abandoned before a response arrived, e.g. because the [Driver] is shutting
+    /// down
+    Cancelled,
+
+    /// Anything else, e.g. a TDLib error with another code or a response that could not be parsed
+    Other,
+}
+
+impl Failure {
+    /// # Kind
+    /// Classifies the failure
+    /// # Example
+    /// ```ignore
+    /// match driver.getChat(chat_id).await {
+    ///     Err(failure) => match failure.kind() {
+    ///         ErrorKind::FloodWait { retry_after } => tokio::time::sleep(retry_after).await,
+    ///         ErrorKind::NotFound => return Ok(None),
+    ///         _ => return Err(failure),
+    ///     },
+    ///     Ok(chat) => return Ok(Some(chat)),
+    /// }
+    /// ```
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::TdLib(Error::error(td_api::error { code, message })) => {
+                match (code, flood_wait(message)) {
+                    (_, Some(retry_after)) => ErrorKind::FloodWait { retry_after },
+                    (400, _) => ErrorKind::BadRequest,
+                    (401, _) => ErrorKind::Unauthorized,
+                    (404, _) => ErrorKind::NotFound,
+                    (500..=599, _) => ErrorKind::Internal,
+                    _ => ErrorKind::Other,
+                }
+            }
+            Self::Receiver(ReceiverError::Timeout(_)) => ErrorKind::Timeout,
+            Self::Receiver(ReceiverError::Channel(_) | ReceiverError::ShuttingDown) => {
+                ErrorKind::Cancelled
+            }
+            Self::Receiver(_) => ErrorKind::Other,
+        }
+    }
+
+    /// Code of the TDLib [Error], if TDLib answered with one
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Self::TdLib(Error::error(td_api::error { code, .. })) => Some(*code),
+            Self::Receiver(_) => None,
+        }
+    }
+
+    /// Message of the TDLib [Error], if TDLib answered with one
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Self::TdLib(Error::error(td_api::error { message, .. })) => Some(message),
+            Self::Receiver(_) => None,
+        }
+    }
+}
+
+/// Parses the wait time out of `FLOOD_WAIT_X` and `Too Many Requests: retry after X`
+fn flood_wait(message: &str) -> Option<Duration> {
+    let (_, seconds) = message
+        .split_once("FLOOD_WAIT_")
+        .or_else(|| message.split_once("retry after "))?;
+    let seconds = seconds
+        .split(|char: char| !char.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TdLib(Error::error(td_api::error { code, message })) => {
+                write!(f, "TDLib error {code}: {message}")
+            }
+            Self::Receiver(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Failure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::TdLib(_) => None,
+            Self::Receiver(err) => err.source(),
+        }
+    }
+}
+
+impl std::fmt::Display for ReceiverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Serde(err) => write!(f, "invalid payload: {err}"),
+            Self::Channel(_) => write!(f, "request was dropped before a response arrived"),
+            Self::QueueFull => write!(f, "a request with the same task id is still pending"),
+            Self::Timeout(_) => write!(f, "request timed out"),
+            Self::ShuttingDown => write!(f, "driver is shutting down"),
+            Self::Empty => write!(f, "request cannot be executed synchronously"),
+            Self::NoManager => write!(f, "no client manager was given to the driver builder"),
+        }
+    }
+}
+
+impl std::error::Error for ReceiverError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Serde(err) => Some(err),
+            Self::Channel(err) => Some(err),
+            Self::Timeout(err) => Some(err),
+            Self::QueueFull | Self::ShuttingDown | Self::Empty | Self::NoManager => None,
+        }
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for Failure {
+    fn from(value: tokio::time::error::Elapsed) -> Self {
+        Self::Receiver(ReceiverError::Timeout(value))
+    }
+}
+
+impl From<tokio::sync::oneshot::error::RecvError> for Failure {
+    fn from(value: tokio::sync::oneshot::error::RecvError) -> Self {
+        Self::Receiver(ReceiverError::Channel(value))
+    }
+}
+
+impl From<serde_json::Error> for Failure {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Receiver(ReceiverError::Serde(value))
+    }
+}
+
+#[test]
+/// TDLib errors are classified by their code, and flood waits by their message
+fn failure_kind() {
+    let failure = |code: i32, message: &str| {
+        Failure::TdLib(Error::error(td_api::error {
+            code,
+            message: message.into(),
+        }))
+    };
+
+    assert_eq!(
+        failure(429, "Too Many Requests: retry after 42").kind(),
+        ErrorKind::FloodWait {
+            retry_after: Duration::from_secs(42)
+        }
+    );
+    assert_eq!(
+        failure(420, "FLOOD_WAIT_7").kind(),
+        ErrorKind::FloodWait {
+            retry_after: Duration::from_secs(7)
+        }
+    );
+    assert_eq!(
+        failure(400, "CHAT_ID_INVALID").kind(),
+        ErrorKind::BadRequest
+    );
+    assert_eq!(failure(401, "Unauthorized").kind(), ErrorKind::Unauthorized);
+    assert_eq!(failure(404, "Not Found").kind(), ErrorKind::NotFound);
+    assert_eq!(failure(500, "Request aborted").kind(), ErrorKind::Internal);
+    assert_eq!(
+        failure(503, "Service Unavailable").kind(),
+        ErrorKind::Internal
+    );
+    assert_eq!(failure(406, "").kind(), ErrorKind::Other);
+    assert_eq!(
+        Failure::Receiver(ReceiverError::ShuttingDown).kind(),
+        ErrorKind::Cancelled
+    );
+
+    let failure = failure(404, "Not Found");
+    assert_eq!(failure.code(), Some(404));
+    assert_eq!(failure.to_string(), "TDLib error 404: Not Found");
+}
@@ -0,0 +1,270 @@
+use super::*;
+use futures::Stream;
+use tokio::sync::watch;
+
+/// # Transfer
+/// Direction of a file transfer that TDLib reports on through `updateFile`, i.e. a download or an
+/// upload. Only what differs between the two lives here; [FileWatch] and [Tracker] follow the
+/// transfer.
+pub(crate) trait Transfer {
+    type Progress: Copy + Default + Send + Sync + 'static;
+    type Error: Clone + From<Failure> + Send + Sync + 'static;
+
+    /// The transfer was cancelled through [FileWatch::cancel]
+    const CANCELLED: Self::Error;
+
+    /// TDLib stopped the transfer before it was complete
+    const INTERRUPTED: Self::Error;
+
+    /// The [Driver] went away before the transfer completed
+    const CLOSED: Self::Error;
+
+    /// Whether TDLib may not report the transfer as active yet right after it has been started
+    const STARTS_IDLE: bool = false;
+
+    /// Progress of the transfer of `file`
+    fn progress(file: &file) -> Self::Progress;
+
+    /// Whether `file` has been transferred completely
+    fn is_completed(file: &file) -> bool;
+
+    /// Whether TDLib is transferring `file`
+    fn is_active(file: &file) -> bool;
+
+    /// Stops the transfer of the file with `file_id`
+    async fn cancel(&self, driver: &Driver, file_id: i32) -> Result<(), Failure>;
+
+    /// The file with `file_id` as of `update`, if an update other than `updateFile` tells how far
+    /// its transfer is
+    async fn resolve(
+        &self,
+        _driver: &Driver,
+        _update: &Update,
+        _file_id: i32,
+    ) -> Result<Option<file>, Failure> {
+        Result::Ok(None)
+    }
+}
+
+/// Where a transfer stands
+#[derive(Debug, Clone)]
+pub(crate) enum Status<Progress, Error> {
+    /// Not started yet, e.g. waiting for a free slot
+    Queued,
+    Running(Progress),
+    Completed(Progress, Box<file>),
+    Failed(Error),
+}
+
+/// # File Watch
+/// Handle side of a transfer: reports the [Status] that its [Tracker] publishes, and asks it to
+/// cancel
+pub(crate) struct FileWatch<T: Transfer> {
+    status: watch::Receiver<Status<T::Progress, T::Error>>,
+    cancel: watch::Sender<bool>,
+}
+
+/// # Tracker
+/// Task side of a transfer: publishes the [Status] of the transfer to its [FileWatch]
+pub(crate) struct Tracker<T: Transfer> {
+    status: watch::Sender<Status<T::Progress, T::Error>>,
+    cancelled: watch::Receiver<bool>,
+}
+
+impl<T: Transfer> FileWatch<T> {
+    /// Both sides of a transfer that starts out with `status`
+    pub(crate) fn new(status: Status<T::Progress, T::Error>) -> (Self, Tracker<T>) {
+        let (status, status_receiver) = watch::channel(status);
+        let (cancel, cancelled) = watch::channel(false);
+        let watch = Self {
+            status: status_receiver,
+            cancel,
+        };
+        (watch, Tracker { status, cancelled })
+    }
+
+    /// Latest progress of the transfer
+    pub(crate) fn current(&self) -> T::Progress {
+        match &*self.status.borrow() {
+            Status::Running(progress) | Status::Completed(progress, _) => *progress,
+            Status::Queued | Status::Failed(_) => T::Progress::default(),
+        }
+    }
+
+    /// [Stream] of the progress of the transfer, starting with the latest; ends once the transfer
+    /// has completed or failed
+    pub(crate) fn progress(&self) -> impl Stream<Item = T::Progress> + Send + Unpin + 'static {
+        let status = self.status.clone();
+        Box::pin(futures::stream::unfold(
+            (status, true),
+            |(mut status, first)| async move {
+                if !first {
+                    status.changed().await.ok()?;
+                }
+                let progress = match &*status.borrow_and_update() {
+                    Status::Queued => T::Progress::default(),
+                    Status::Running(progress) | Status::Completed(progress, _) => *progress,
+                    Status::Failed(_) => return None,
+                };
+                Some((progress, (status, false)))
+            },
+        ))
+    }
+
+    /// Waits for the transfer to complete and returns the transferred file
+    pub(crate) async fn wait(&self) -> Result<file, T::Error> {
+        let mut status = self.status.clone();
+        let status = status
+            .wait_for(|status| matches!(status, Status::Completed(..) | Status::Failed(_)))
+            .await
+            .map_err(|_| T::CLOSED)?;
+        match &*status {
+            Status::Completed(_, file) => Result::Ok(file.as_ref().clone()),
+            Status::Failed(err) => Err(err.clone()),
+            Status::Queued | Status::Running(_) => unreachable!(),
+        }
+    }
+
+    /// Asks the [Tracker] to cancel the transfer
+    pub(crate) fn cancel(&self) {
+        self.cancel.send_replace(true);
+    }
+}
+
+impl<T: Transfer> Tracker<T> {
+    /// Waits until the transfer is cancelled
+    pub(crate) async fn cancelled(&mut self) {
+        if self
+            .cancelled
+            .wait_for(|cancelled| *cancelled)
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Publishes the failure of the transfer
+    pub(crate) fn fail(&self, err: T::Error) {
+        let _ = self.status.send(Status::Failed(err));
+    }
+
+    /// # Follow
+    /// Follows the `updateFile`s of `file`, as delivered to `updates`, until it has been
+    /// transferred, and publishes its progress on the way. A cancellation stops the transfer with
+    /// [Transfer::cancel].
+    ///
+    /// *Note*: subscribe to `updates` before starting the transfer, or the first ones may be missed
+    pub(crate) async fn follow(
+        &mut self,
+        transfer: &T,
+        driver: &Driver,
+        mut file: file,
+        mut updates: Subscription,
+    ) -> Result<(), T::Error> {
+        let file_id = file.id;
+        let mut started = !T::STARTS_IDLE;
+        let mut missed = 0;
+        loop {
+            let progress = T::progress(&file);
+            if T::is_completed(&file) {
+                let _ = self
+                    .status
+                    .send(Status::Completed(progress, Box::new(file)));
+                return Result::Ok(());
+            }
+            started |= T::is_active(&file);
+            if started && !T::is_active(&file) {
+                return Err(T::INTERRUPTED);
+            }
+            let _ = self.status.send(Status::Running(progress));
+
+            file = tokio::select! {
+                file = Self::next(transfer, driver, &mut updates, &mut missed, file_id) => file?,
+                _ = self.cancelled() => {
+                    transfer.cancel(driver, file_id).await?;
+                    return Err(T::CANCELLED);
+                }
+            };
+        }
+    }
+
+    /// Waits for the next `updateFile` of `file_id`, or for another update that
+    /// [Transfer::resolve]s it
+    async fn next(
+        transfer: &T,
+        driver: &Driver,
+        updates: &mut Subscription,
+        missed: &mut u64,
+        file_id: i32,
+    ) -> Result<file, T::Error> {
+        loop {
+            let Some(update) = updates.recv().await else {
+                return Err(T::CLOSED);
+            };
+            if let Update::updateFile(update) = update.as_ref() {
+                if update.file.id == file_id {
+                    return Result::Ok(update.file.clone());
+                }
+            }
+            if let Some(file) = transfer.resolve(driver, &update, file_id).await? {
+                return Result::Ok(file);
+            }
+
+            // NOTE: updates were skipped, so the latest one may be among them
+            if updates.missed() != *missed {
+                *missed = updates.missed();
+                let File::file(file) = driver.getFile(file_id).await?;
+                return Result::Ok(file);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+/// How far one side of a [file_json] fixture, local or remote, has been transferred
+pub(crate) struct Transferred {
+    pub bytes: i64,
+    pub active: bool,
+}
+
+#[cfg(test)]
+impl Transferred {
+    /// `bytes` transferred, and TDLib is transferring the rest
+    pub(crate) fn active(bytes: i64) -> Self {
+        Self {
+            bytes,
+            active: true,
+        }
+    }
+
+    /// `bytes` transferred, and TDLib is not transferring the rest
+    pub(crate) fn idle(bytes: i64) -> Self {
+        Self {
+            bytes,
+            active: false,
+        }
+    }
+}
+
+#[cfg(test)]
+/// A `file` of `size` bytes, `local`ly downloaded and `remote`ly uploaded as far as given. A side
+/// is complete, and no longer active, once all `size` bytes have been transferred.
+pub(crate) fn file_json(id: i32, size: i64, local: Transferred, remote: Transferred) -> Value {
+    let downloaded = local.bytes == size;
+    let uploaded = remote.bytes == size;
+    let path = match downloaded {
+        true => format!("/files/{id}"),
+        false => String::new(),
+    };
+    serde_json::json!({
+        "@type": "file", "id": id, "size": size, "expected_size": size,
+        "local": { "@type": "localFile", "path": path, "can_be_downloaded": true,
+            "can_be_deleted": false, "is_downloading_active": local.active && !downloaded,
+            "is_downloading_completed": downloaded, "download_offset": 0,
+            "downloaded_prefix_size": local.bytes, "downloaded_size": local.bytes },
+        "remote": { "@type": "remoteFile", "id": "", "unique_id": "",
+            "is_uploading_active": remote.active && !uploaded, "is_uploading_completed": uploaded,
+            "uploaded_size": remote.bytes },
+    })
+}
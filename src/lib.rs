@@ -2,58 +2,202 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
-use std::ffi::CStr;
-use std::ffi::CString;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
 pub use td_api::Api;
 pub use td_api::*;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot::*;
-use tokio::task::JoinHandle;
 
+pub mod auth;
+mod builder;
+mod confirm;
+mod dispatch;
+pub mod download;
+#[cfg(feature = "tdjson")]
+mod executor;
+mod failure;
+mod file_watch;
+mod listener;
+#[cfg(feature = "tdjson")]
+pub mod logging;
+mod manager;
+mod middleware;
+pub mod notifier;
+pub mod paginate;
+pub mod query;
+mod rate_limit;
+mod record;
+mod retry;
+mod shutdown;
+mod state;
+mod subscription;
 mod td_api;
+#[cfg(feature = "tdjson")]
 mod tdlib;
-
-#[no_mangle]
-unsafe extern "C" fn log(
-    _verbosity_level: ::std::os::raw::c_int,
-    _message: *const ::std::os::raw::c_char,
-) {
-    // let message = CStr::from_ptr(message);
-    // let message = message.to_str().unwrap();
-    // println!("{message}");
-}
+pub mod telemetry;
+mod transport;
+pub mod upload;
+
+pub use auth::authorize;
+pub use auth::AuthError;
+pub use auth::Authenticator;
+use builder::Config;
+pub use builder::DriverBuilder;
+pub use builder::TdlibParameters;
+pub use builder::DEFAULT_TIMEOUT;
+pub use confirm::SendError;
+pub use dispatch::ChatKind;
+pub use dispatch::Command;
+pub use dispatch::CommandContext;
+pub use dispatch::Dispatcher;
+pub use download::Download;
+pub use download::DownloadError;
+pub use download::DownloadProgress;
+pub use download::Downloads;
+#[cfg(feature = "tdjson")]
+pub use executor::Executor;
+pub use failure::ErrorKind;
+pub use failure::Failure;
+pub use failure::ReceiverError;
+use listener::Listener;
+pub use manager::ClientManager;
+use manager::*;
+pub use middleware::ApiExt;
+pub use middleware::Layered;
+pub use middleware::MapRequest;
+pub use middleware::Middleware;
+pub use middleware::Trace;
+pub use notifier::BoundedNotifier;
+pub use notifier::NotificationReceiver;
+pub use notifier::NotificationSink;
+pub use notifier::Overflow;
+pub use paginate::paginate;
+pub use paginate::Page;
+pub use paginate::Paginated;
+pub use paginate::Paging;
+pub use query::callback_button;
+pub use query::callback_data;
+use query::decode_callback_data;
+use query::decode_payload;
+pub use query::CallbackAnswer;
+pub use query::CallbackContext;
+pub use query::CallbackDataError;
+pub use query::InlineAnswer;
+pub use query::InlineContext;
+pub use query::InlineResult;
+pub use rate_limit::RateLimit;
+pub use rate_limit::RateLimiter;
+pub use record::Direction;
+pub use record::Record;
+pub use record::Recorder;
+pub use record::Replay;
+pub use retry::RetryPolicy;
+pub use retry::DEFAULT_MAX_FLOOD_WAIT;
+pub use retry::DEFAULT_RETRY_ATTEMPTS;
+pub use state::State;
+pub use subscription::Subscription;
+pub use subscription::DEFAULT_UPDATE_CAPACITY;
+use subscription::*;
+pub use transport::MemoryTransport;
+#[cfg(feature = "tdjson")]
+pub use transport::Tdjson;
+pub use transport::Transport;
+pub use upload::Upload;
+pub use upload::UploadError;
+pub use upload::UploadProgress;
+pub use upload::UploadSource;
 
 pub type Notifier = UnboundedSender<Notification>;
-type Tasks = HashMap<usize, Sender<Value>>;
+type Tasks = HashMap<usize, Task>;
 type TasksSync = Arc<Mutex<Tasks>>;
 
+/// Source of task ids. Ids are never reused (short of the counter wrapping around), so a response
+/// can only ever be delivered to the request that caused it.
+static TASK_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// A request waiting for its response
+struct Task {
+    /// Channel to the waiting request
+    sender: Sender<Value>,
+
+    /// `@extra` supplied by the caller, which is restored in the response
+    extra: Option<Value>,
+
+    /// Point in time after which the request has timed out
+    deadline: std::time::Instant,
+}
+
+/// # Task Guard
+/// Removes a [Task] from the queue when the request that created it goes away, whether it
+/// completed, timed out or the caller dropped the future. A response that arrives afterwards is
+/// reported as [Notification::LateResponse].
+struct TaskGuard<'tasks> {
+    client_id: i32,
+    tasks: &'tasks TasksSync,
+    task_id: usize,
+    /// Woken once the last task is gone, see [Driver::shutdown]
+    drained: &'tasks tokio::sync::Notify,
+}
+
+impl Drop for TaskGuard<'_> {
+    fn drop(&mut self) {
+        // NOTE: safe to unwrap because poison cleared
+        while self.tasks.is_poisoned() {
+            self.tasks.clear_poison();
+        }
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.remove(&self.task_id);
+        if tasks.is_empty() {
+            self.drained.notify_waiters();
+        }
+        telemetry::request_finished(self.client_id);
+    }
+}
+
 #[allow(dead_code)]
 /// # Driver
 /// [Driver] wraps the underlying `tdlib` and associated API and provides quality of life public
 /// methods:
 /// - [Driver::new]: Does all the plumbing to create a td client, a task queue and a notification
 /// handler.
+/// - [DriverBuilder]: The same, with timeouts and startup settings configured at runtime.
 /// *Note*: Plumbing for sending is called through the [Api] traitt
 /// # Safety
 /// The [Driver] needs to be wrapped in an [std::sync::Arc] in order to become thread safe.
-pub struct Driver<const TIMEOUT_MS: usize> {
+pub struct Driver {
     id: i32,
-    reference_point: std::time::Instant,
     tasks: TasksSync,
-    listener_handle: JoinHandle<()>,
+    updates: UpdateFeed,
+    config: Config,
+    /// Set once the driver stops accepting requests, see [Driver::shutdown]
+    closing: AtomicBool,
+    /// Woken whenever the last in-flight request goes away
+    drained: tokio::sync::Notify,
+    /// Woken once [Driver::shutdown] has stopped accepting requests
+    closed: tokio::sync::Notify,
+    /// Handle to the receive loop; the client is deregistered from it on drop
+    manager: ClientManager,
 }
 
-impl<const TIMEOUT_MS: usize> Drop for Driver<TIMEOUT_MS> {
+impl Drop for Driver {
     fn drop(&mut self) {
-        self.listener_handle.abort();
+        // Close the client unless [Driver::shutdown] already has; nobody is left to wait for the
+        // response
+        if !self.closing.swap(true, Ordering::AcqRel) {
+            self.send_sync(serde_json::json!({ "@type": "close" }));
+        }
+        self.manager.deregister(self.id);
     }
 }
 
-impl<const TIMEOUT_MS: usize> Driver<TIMEOUT_MS> {
+impl Driver {
     /// # New
-    /// Create a new [Driver].
+    /// Create a new [Driver] on the process-wide [ClientManager], with the default settings of a
+    /// [DriverBuilder].
     /// Arguments:
     /// - `notification_handler`: Option of a notification handler to call
     /// # Example
@@ -67,150 +211,122 @@ impl<const TIMEOUT_MS: usize> Driver<TIMEOUT_MS> {
     ///     println!("{update:#?}");
     /// });
     ///
-    /// let driver = Driver::<1000>::new(Some(sender));
+    /// let driver = Driver::new(Some(sender));
     ///
     /// let payload = driver.getAuthorizationState().await.unwrap();
     /// println!("{payload:#?}");
     /// ```
+    #[cfg(feature = "tdjson")]
     pub fn new(notifier: Option<Notifier>) -> Self {
-        // Create a client
-        let id = unsafe { tdlib::td_create_client_id() };
-
-        // Clear tdlib's log stream
-        Self::clear_log_stream(id);
-        // Implement our own log stream
-        unsafe {
-            let callback: tdlib::td_log_message_callback_ptr = Some(log);
-            tdlib::td_set_log_message_callback(1024, callback);
-        };
-
-        // Start a listening loop
-        let tasks = Arc::new(Mutex::new(HashMap::new()));
-        let listener_handle = tokio::task::spawn(Self::start(tasks.clone(), notifier));
+        ClientManager::global().client(notifier)
+    }
 
+    /// Wraps a client that has been registered with `manager`
+    pub(crate) fn from_parts(
+        id: i32,
+        manager: ClientManager,
+        tasks: TasksSync,
+        updates: UpdateFeed,
+        config: Config,
+    ) -> Self {
         // Create driver
         Self {
             id,
             tasks,
-            reference_point: std::time::Instant::now(),
-            listener_handle,
+            updates,
+            config,
+            closing: AtomicBool::new(false),
+            drained: tokio::sync::Notify::new(),
+            closed: tokio::sync::Notify::new(),
+            manager,
         }
     }
 
-    /// Starts a loop that listens for messages from TDLIB api and sends it to the relevant
-    /// [Driver::tasks];
-    async fn start(tasks: TasksSync, notifier: Option<Notifier>) {
-        loop {
-            // Yield to [tokio] executor to prevent blocking
-            tokio::task::yield_now().await;
-
-            // Listen for messages
-            let timeout = TIMEOUT_MS as f64 / 1000f64;
-            let payload = unsafe { tdlib::td_receive(timeout).as_ref() };
-            let payload =
-                payload.map(|ptr| unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string());
-
-            // Payload is an Option:
-            // - No payload; continue looping
-            let payload = payload.map(|output| serde_json::from_str::<Value>(&output));
-
-            // Message handler
-            match payload {
-                // If payload is empty continue loop
-                None => continue,
-
-                // If paylod validly converts to JSON
-                Some(Ok(payload)) => {
-                    // Obtain task_id
-                    let task_id = payload["@extra"].as_u64();
-
-                    // Check if there is a type ID
-                    match task_id {
-                        Some(task_id) => {
-                            // Attempt to notify the task
-                            if let Err(err) = Self::notify_task(tasks.clone(), task_id, payload) {
-                                if let Some(handler) = &notifier {
-                                    let _ = handler.send(Notification::PacketDropped(task_id, err));
-                                }
-                            }
-                        }
-                        None => {
-                            if let Some(handler) = &notifier {
-                                let _ = handler.send(Notification::Driver(payload));
-                            }
-                        }
-                    }
-                }
+    /// # Subscribe
+    /// Subscribes to every [Update] of this client. Any number of subscriptions can be open at
+    /// once; each of them sees every update from the moment it was created.
+    pub fn subscribe(&self) -> Subscription {
+        Subscription::new(&self.updates, None)
+    }
 
-                // If payload does not convert to JSON: notify the client
-                Some(Err(err)) => {
-                    if let Some(handler) = &notifier {
-                        // NOTE: Sender failure means that the receiver has dropped, we do not need
-                        // to parse that event.
-                        let _ = handler.send(Notification::ApiError(err));
-                    }
-                }
-            }
-        }
+    /// # Subscribe To
+    /// Subscribes to the [Update]s of this client whose `@type` is one of `kinds`, e.g.
+    /// `updateNewMessage` or `updateAuthorizationState`.
+    pub fn subscribe_to<Kind: Into<String>>(
+        &self,
+        kinds: impl IntoIterator<Item = Kind>,
+    ) -> Subscription {
+        let kinds = kinds.into_iter().map(Into::into).collect();
+        Subscription::new(&self.updates, Some(kinds))
     }
 
-    /// Retrieve and notify the [Driver::tasks]
-    fn notify_task(tasks: TasksSync, task_id: u64, payload: Value) -> Result<(), Value> {
-        let task_id = task_id as usize;
+    /// # State
+    /// The [State] the driver keeps up to date, if it was built with [DriverBuilder::state]
+    pub fn state(&self) -> Option<&State> {
+        self.config.state.as_ref()
+    }
 
-        // Retrieve oneshot sender into hashmap
+    /// Number of requests waiting for a response
+    pub fn pending(&self) -> usize {
         // NOTE: safe to unwrap because poison cleared
-        while tasks.is_poisoned() {
-            tasks.clear_poison();
+        while self.tasks.is_poisoned() {
+            self.tasks.clear_poison();
         }
+        self.tasks.lock().unwrap().len()
+    }
 
-        // Send value to task
-        match tasks.lock().unwrap().remove(&task_id) {
-            Some(sender) => sender.send(payload),
-            None => Err(payload),
+    /// # With Timeout
+    /// Returns a handle to this [Driver] whose requests time out after `timeout`, overriding the
+    /// default and per-method timeouts for the calls made through it.
+    /// # Example
+    /// ```ignore
+    /// let file = driver
+    ///     .with_timeout(Duration::from_secs(600))
+    ///     .downloadFile(file_id, 1, 0, 0, true)
+    ///     .await?;
+    /// ```
+    pub fn with_timeout(&self, timeout: std::time::Duration) -> Timed<'_> {
+        Timed {
+            driver: self,
+            timeout,
         }
     }
 
     /// Send raw payload string
-    fn send_sync<Payload: Serialize>(id: i32, payload: Payload) {
+    fn send_sync<Payload: Serialize>(&self, payload: Payload) {
         let payload = serde_json::to_string(&payload).unwrap();
-        let payload = CString::new(payload).unwrap();
-        unsafe {
-            tdlib::td_send(id, payload.as_ptr());
-        }
+        self.manager.transport().send(self.id, &payload);
     }
+}
 
-    /// Clears the internal log stream
-    fn clear_log_stream(id: i32) {
-        // Create payload for empty log stream
-        let log_stream = LogStream::logStreamEmpty;
-        let log_stream = serde_json::json!({
-            "@type": "setLogStream",
-            "log_stream": log_stream
-        });
-
-        Self::send_sync(id, log_stream);
+impl Driver {
+    /// Fails once the driver has stopped accepting requests
+    fn accepting(&self) -> Result<(), Failure> {
+        match self.closing.load(Ordering::Acquire) {
+            true => Err(Failure::Receiver(ReceiverError::ShuttingDown)),
+            false => Ok(()),
+        }
     }
-}
 
-impl<const TIMEOUT_MS: usize> Api for Driver<TIMEOUT_MS> {
-    /// Sends the `payload` to the TDLIB api.
-    /// *Arguments*:
-    /// - `payload`: payload as a [::std::string::String]
-    async fn send<Payload: Serialize, Target: DeserializeOwned>(
+    /// Sends the `payload` to the TDLIB api and waits up to `timeout` for the response.
+    async fn request<Payload: Serialize, Target: DeserializeOwned>(
         &self,
         payload: Payload,
+        timeout: std::time::Duration,
     ) -> Result<Target, Failure> {
         // Create JSON payload
         let mut payload = serde_json::to_value(payload).unwrap();
+        let method = payload["@type"].as_str().unwrap_or_default().to_string();
 
-        // Create oneshot channel; task number based on time_hash
-        let task_id = {
-            let task_id = std::time::Instant::now() - self.reference_point;
-            task_id.as_nanos() as usize // Same as % operation
-        };
+        // Create oneshot channel; task number drawn from the counter
+        let task_id = TASK_ID.fetch_add(1, Ordering::Relaxed);
         let (sender, receiver) = channel();
 
+        // Keep the caller's own `@extra`, if any, so it can be handed back with the response
+        let extra = payload
+            .as_object_mut()
+            .and_then(|payload| payload.remove("@extra"));
+
         // Insert oneshot sender into hashmap
         // NOTE: safe to unwrap because poison cleared
         while self.tasks.is_poisoned() {
@@ -223,83 +339,114 @@ impl<const TIMEOUT_MS: usize> Api for Driver<TIMEOUT_MS> {
                     Err(Failure::Receiver(ReceiverError::QueueFull))
                 }
                 std::collections::hash_map::Entry::Vacant(vacant_entry) => {
-                    vacant_entry.insert(sender);
+                    vacant_entry.insert(Task {
+                        sender,
+                        extra,
+                        deadline: std::time::Instant::now() + timeout,
+                    });
                     Ok(())
                 }
             }
         }?;
+        telemetry::request_started(self.id);
+        let _guard = TaskGuard {
+            client_id: self.id,
+            tasks: &self.tasks,
+            task_id,
+            drained: &self.drained,
+        };
 
         // Append task to payload
         // NOTE: unwrap because usize always validly converts
         payload["@extra"] = serde_json::to_value(task_id).unwrap();
 
         // Send payload
-        Self::send_sync(self.id, payload);
+        let start = std::time::Instant::now();
+        self.send_sync(payload);
+
+        let result = Self::response(receiver, timeout).await;
+        telemetry::request(
+            self.id,
+            &method,
+            start.elapsed(),
+            result.as_ref().map(|_| ()),
+        );
+        result
+    }
 
+    /// Waits up to `timeout` for the response on `receiver`
+    async fn response<Target: DeserializeOwned>(
+        receiver: Receiver<Value>,
+        timeout: std::time::Duration,
+    ) -> Result<Target, Failure> {
         // Wait on receiver
-        let timeout = TIMEOUT_MS as f64 / 1000f64;
-        let result =
-            tokio::time::timeout(std::time::Duration::from_secs_f64(timeout), receiver).await??;
-
-        // Get output
-        match serde_json::from_value(result.clone()) {
-            Result::Ok(value) => Ok(value),
-            Err(_) => match serde_json::from_value(result) {
-                Result::Ok(error) => Err(Failure::TdLib(error)),
-                Err(err) => Err(err.into()),
-            },
+        let result = tokio::time::timeout(timeout, receiver).await??;
+
+        // Get output; an error is never handed out as a `Target`, even if it would parse as one
+        match result["@type"].as_str() {
+            Some("error") => Err(Failure::TdLib(serde_json::from_value(result)?)),
+            _ => Result::Ok(serde_json::from_value(result)?),
         }
     }
 }
 
+impl Api for Driver {
+    /// Sends the `payload` to the TDLIB api.
+    /// *Arguments*:
+    /// - `payload`: payload as a [::std::string::String]
+    async fn send<Payload: Serialize, Target: DeserializeOwned>(
+        &self,
+        payload: Payload,
+    ) -> Result<Target, Failure> {
+        self.accepting()?;
+        let payload = serde_json::to_value(payload).unwrap();
+        let timeout = self.config.timeout(payload["@type"].as_str());
+        self.retrying(payload, timeout).await
+    }
+}
+
+/// # Timed
+/// Handle to a [Driver] with a fixed request timeout, created by [Driver::with_timeout]
+pub struct Timed<'driver> {
+    driver: &'driver Driver,
+    timeout: std::time::Duration,
+}
+
+impl Api for Timed<'_> {
+    async fn send<Payload: Serialize, Target: DeserializeOwned>(
+        &self,
+        payload: Payload,
+    ) -> Result<Target, Failure> {
+        self.driver.accepting()?;
+        let payload = serde_json::to_value(payload).unwrap();
+        self.driver.retrying(payload, self.timeout).await
+    }
+}
+
 #[derive(Debug)]
 pub enum Notification {
-    /// Packet that does not have a task allocated to it
+    /// Packet that does not have a task allocated to it and is not an [Update]
     Driver(Value),
 
+    /// An [Update]; the same update is handed to every [Subscription] of the client
+    Update(Arc<Update>),
+
     /// A packet that has dropped becuase the calling function's task did not make it into the
     /// hashmap
     PacketDropped(u64, Value),
 
+    /// A response to a request that has already timed out or been cancelled. The task id was
+    /// issued by this process and will never be reused, so the response is not delivered to any
+    /// other request.
+    LateResponse(u64, Value),
+
     /// The [Driver] has encountered a payload which it is unable to process. This likely means
     /// that the telegram API is issuing new object classes that the existing handler is unable to
-    /// process.
-    ApiError(serde_json::Error),
-}
-
-#[derive(Debug)]
-pub enum Failure {
-    TdLib(Error),
-    Receiver(ReceiverError),
-}
-
-#[derive(Debug)]
-pub enum ReceiverError {
-    Serde(serde_json::Error),
-    Channel(tokio::sync::oneshot::error::RecvError),
-    QueueFull,
-    Timeout(tokio::time::error::Elapsed),
-    TdLib(Error),
-}
-
-impl From<tokio::time::error::Elapsed> for Failure {
-    fn from(value: tokio::time::error::Elapsed) -> Self {
-        Self::Receiver(ReceiverError::Timeout(value))
-    }
-}
-
-impl From<tokio::sync::oneshot::error::RecvError> for Failure {
-    fn from(value: tokio::sync::oneshot::error::RecvError) -> Self {
-        Self::Receiver(ReceiverError::Channel(value))
-    }
-}
-
-impl From<serde_json::Error> for Failure {
-    fn from(value: serde_json::Error) -> Self {
-        Self::Receiver(ReceiverError::Serde(value))
-    }
+    /// process. The payload cannot be attributed to a client, so every client is notified.
+    ApiError(Arc<serde_json::Error>),
 }
 
+#[cfg(feature = "tdjson")]
 #[tokio::test]
 /// Run the driver with a handler
 async fn driver_with_handler() {
@@ -310,16 +457,17 @@ async fn driver_with_handler() {
         println!("{update:#?}");
     });
 
-    let driver = Driver::<1000>::new(Some(sender));
+    let driver = Driver::new(Some(sender));
 
     let payload = driver.getAuthorizationState().await.unwrap();
     println!("{payload:#?}");
 }
 
+#[cfg(feature = "tdjson")]
 #[tokio::test]
 /// Run the driver and send a couple of requests
 async fn driver() {
-    let driver = Driver::<1000>::new(None);
+    let driver = Driver::new(None);
 
     let payload = driver.getAuthorizationState().await.unwrap();
     println!("{payload:#?}");
@@ -331,6 +479,157 @@ async fn driver() {
     println!("{payload:#?}");
 }
 
+#[cfg(feature = "tdjson")]
+#[tokio::test]
+/// Build a driver at runtime and override timeouts per method and per call
+async fn driver_builder() {
+    let driver = DriverBuilder::new()
+        .timeout(std::time::Duration::from_secs(1))
+        .method_timeout("getAuthorizationState", std::time::Duration::from_secs(5))
+        .log_verbosity(1)
+        .build()
+        .await
+        .unwrap();
+
+    let payload = driver.getAuthorizationState().await.unwrap();
+    println!("{payload:#?}");
+
+    let payload = driver
+        .with_timeout(std::time::Duration::from_secs(2))
+        .getAuthorizationState()
+        .await
+        .unwrap();
+    println!("{payload:#?}");
+}
+
+#[cfg(not(feature = "tdjson"))]
+#[tokio::test]
+/// Without `tdjson` there is no global manager to fall back on
+async fn driver_builder_without_manager() {
+    let result = DriverBuilder::new().build().await;
+    assert!(matches!(
+        result,
+        Err(Failure::Receiver(ReceiverError::NoManager))
+    ));
+}
+
+#[cfg(feature = "tdjson")]
+#[tokio::test]
+/// Run several drivers in one process; each must receive its own responses
+async fn driver_multi_client() {
+    let manager = ClientManager::global();
+    let first = manager.client(None);
+    let second = manager.client(None);
+
+    for _ in 0..3 {
+        let (first, second) = tokio::join!(
+            first.getAuthorizationState(),
+            second.getAuthorizationState()
+        );
+        first.unwrap();
+        second.unwrap();
+    }
+}
+
+#[cfg(feature = "tdjson")]
+#[tokio::test]
+/// Fire many requests at once; none of them may collide on a task id
+async fn driver_concurrent() {
+    let driver = Arc::new(Driver::new(None));
+
+    let mut requests = tokio::task::JoinSet::new();
+    for _ in 0..64 {
+        let driver = driver.clone();
+        requests.spawn(async move { driver.getAuthorizationState().await });
+    }
+    while let Some(result) = requests.join_next().await {
+        result.unwrap().unwrap();
+    }
+}
+
+#[cfg(feature = "tdjson")]
+#[tokio::test]
+/// A caller supplied `@extra` is handed back with the response
+async fn driver_extra() {
+    let payload = serde_json::json!({
+        "@type": "getAuthorizationState",
+        "@extra": { "correlation": "abc" },
+    });
+
+    let driver = Driver::new(None);
+
+    let payload = driver.send::<Value, Value>(payload).await.unwrap();
+    assert_eq!(
+        payload["@extra"],
+        serde_json::json!({ "correlation": "abc" })
+    );
+}
+
+#[cfg(feature = "tdjson")]
+#[tokio::test]
+/// Time out and cancel a storm of requests; the task queue must drain back to empty
+async fn driver_cancellation_storm() {
+    let driver = Arc::new(Driver::new(None));
+
+    // Requests that time out before TDLib gets a chance to respond
+    for _ in 0..256 {
+        let request = driver.with_timeout(std::time::Duration::ZERO);
+        let _ = request.getAuthorizationState().await;
+    }
+    assert_eq!(driver.pending(), 0);
+
+    // Requests whose future is dropped mid-flight
+    let mut requests = tokio::task::JoinSet::new();
+    for _ in 0..256 {
+        let driver = driver.clone();
+        requests.spawn(async move { driver.getAuthorizationState().await });
+    }
+    requests.abort_all();
+    while requests.join_next().await.is_some() {}
+    assert_eq!(driver.pending(), 0);
+
+    // The driver is still usable afterwards
+    driver.getAuthorizationState().await.unwrap();
+}
+
+#[cfg(feature = "tdjson")]
+#[tokio::test]
+/// Several subscribers receive the same typed updates
+async fn driver_subscriptions() {
+    let driver = Driver::new(None);
+    let mut all = driver.subscribe();
+    let mut options = driver.subscribe_to(["updateOption"]);
+
+    driver.getAuthorizationState().await.unwrap();
+
+    let timeout = std::time::Duration::from_secs(5);
+    let update = tokio::time::timeout(timeout, all.recv()).await.unwrap();
+    println!("{update:#?}");
+
+    let update = tokio::time::timeout(timeout, options.recv()).await.unwrap();
+    assert!(matches!(update.as_deref(), Some(Update::updateOption(_))));
+}
+
+#[cfg(feature = "tdjson")]
+#[tokio::test]
+/// Shut a driver down; requests made afterwards are rejected
+async fn driver_shutdown() {
+    let driver = Driver::new(None);
+    driver.getAuthorizationState().await.unwrap();
+
+    driver
+        .shutdown(std::time::Duration::from_secs(10))
+        .await
+        .unwrap();
+
+    let result = driver.getAuthorizationState().await;
+    assert!(matches!(
+        result,
+        Err(Failure::Receiver(ReceiverError::ShuttingDown))
+    ));
+}
+
+#[cfg(feature = "tdjson")]
 #[tokio::test]
 /// # Test of the driver interface
 /// Test to create a [Driver] and send a simple authorization request
@@ -339,7 +638,7 @@ async fn driver_handroll() {
         "@type": "getAuthorizationState",
     });
 
-    let driver = Driver::<1000>::new(None);
+    let driver = Driver::new(None);
 
     let payload = driver
         .send::<Value, AuthorizationState>(payload)
@@ -349,6 +648,29 @@ async fn driver_handroll() {
     println!("{payload:#?}");
 }
 
+#[cfg(feature = "tdjson")]
+#[test]
+/// Call synchronous functions without a client
+fn executor() {
+    let TextEntities::textEntities(textEntities { entities }) = Executor
+        .getTextEntities("@telegram https://telegram.org".into())
+        .unwrap();
+    assert_eq!(entities.len(), 2);
+
+    let result = Executor.testReturnError(td_api::error {
+        code: 400,
+        message: "BAD_REQUEST".into(),
+    });
+    assert!(matches!(
+        result,
+        Err(Failure::TdLib(Error::error(td_api::error {
+            code: 400,
+            ..
+        })))
+    ));
+}
+
+#[cfg(feature = "tdjson")]
 #[test]
 /// # Test of the C interface
 /// Simple test loop to create a client, set a logging callbck, send a request for
@@ -359,7 +681,7 @@ fn c_json_client() {
     assert_eq!(client, 1);
 
     // Set logging callback
-    let callback: tdlib::td_log_message_callback_ptr = Some(log);
+    let callback: tdlib::td_log_message_callback_ptr = Some(logging::forward);
     unsafe { tdlib::td_set_log_message_callback(1024, callback) };
 
     let send = serde_json::json!({
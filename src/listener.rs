@@ -0,0 +1,284 @@
+use super::*;
+use serde::Deserialize;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often the receive loop sweeps the task queues for stale entries
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// # Listener
+/// Owns the dedicated OS thread that polls [Transport::receive], i.e. `td_receive`.
+///
+/// `td_receive` blocks for up to `timeout`, so it must never run on a [tokio] worker: on a
+/// current-thread runtime that would freeze every other task. The thread looks up the client of
+/// each payload in [Routes] and hands it over to the async side through the oneshot channels in
+/// [TasksSync] and the [Notifier] of that client.
+pub(crate) struct Listener {
+    stop: Arc<AtomicBool>,
+    /// Poll timeout in microseconds
+    timeout: Arc<AtomicU64>,
+    _handle: JoinHandle<()>,
+}
+
+impl Listener {
+    /// Spawns the receiver thread.
+    /// Arguments:
+    /// - `timeout`: how long a single `td_receive` call may block, which is also the upper bound
+    ///   on how long the thread takes to notice a stop signal
+    /// - `routes`: clients to deliver payloads to, keyed by `@client_id`
+    /// - `transport`: interface to receive payloads from
+    pub(crate) fn spawn(timeout: Duration, routes: Routes, transport: Arc<dyn Transport>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let timeout = Arc::new(AtomicU64::new(timeout.as_micros() as u64));
+        let handle = std::thread::Builder::new()
+            .name("tdlib-receiver".into())
+            .spawn({
+                let stop = stop.clone();
+                let timeout = timeout.clone();
+                move || Self::listen(timeout, stop, routes, transport)
+            })
+            .expect("failed to spawn tdlib receiver thread");
+
+        Self {
+            stop,
+            timeout,
+            _handle: handle,
+        }
+    }
+
+    /// Signals the receiver thread to stop. The thread exits after the `td_receive` call in
+    /// flight returns, without blocking the caller.
+    pub(crate) fn stop(&self) {
+        self.stop.store(true, Ordering::Release);
+    }
+
+    /// Changes the poll timeout, starting with the next `td_receive` call
+    pub(crate) fn set_timeout(&self, timeout: Duration) {
+        self.timeout
+            .store(timeout.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Loop that listens for messages from TDLIB api and sends it to the relevant [Route]
+    fn listen(
+        timeout: Arc<AtomicU64>,
+        stop: Arc<AtomicBool>,
+        routes: Routes,
+        transport: Arc<dyn Transport>,
+    ) {
+        let mut last_sweep = Instant::now();
+
+        while !stop.load(Ordering::Acquire) {
+            // Sweep stale tasks
+            if last_sweep.elapsed() >= SWEEP_INTERVAL {
+                Self::sweep(&routes);
+                last_sweep = Instant::now();
+            }
+
+            // Listen for messages
+            let timeout = Duration::from_micros(timeout.load(Ordering::Relaxed));
+            let payload = transport.receive(timeout);
+
+            // Payload is an Option:
+            // - No payload; continue looping
+            let payload = payload.map(|output| serde_json::from_str::<Value>(&output));
+
+            // Message handler
+            match payload {
+                // If payload is empty continue loop
+                None => continue,
+
+                // If paylod validly converts to JSON
+                Some(Ok(payload)) => {
+                    // Find the client the payload belongs to; payloads of clients that are gone
+                    // are discarded
+                    let route = payload["@client_id"]
+                        .as_i64()
+                        .and_then(|id| Self::route(&routes, id as i32));
+                    let Some(Route {
+                        tasks,
+                        notifier,
+                        updates,
+                        state,
+                    }) = route
+                    else {
+                        continue;
+                    };
+
+                    // Obtain task_id
+                    let task_id = payload["@extra"].as_u64();
+
+                    // Check if there is a type ID
+                    match task_id {
+                        Some(task_id) => {
+                            // Attempt to notify the task
+                            if let Err(err) = Self::notify_task(&tasks, task_id, payload) {
+                                let notification = if Self::issued(task_id) {
+                                    telemetry::late_response();
+                                    Notification::LateResponse(task_id, err)
+                                } else {
+                                    telemetry::packet_dropped();
+                                    Notification::PacketDropped(task_id, err)
+                                };
+                                if let Some(handler) = &notifier {
+                                    handler.send(notification);
+                                }
+                            }
+                        }
+                        None => {
+                            let client_id = payload["@client_id"].as_i64().unwrap_or_default();
+                            Self::notify_update(
+                                client_id as i32,
+                                &updates,
+                                &notifier,
+                                &state,
+                                payload,
+                            )
+                        }
+                    }
+                }
+
+                // If payload does not convert to JSON there is no way of telling which client it
+                // belongs to: notify all of them
+                Some(Err(err)) => {
+                    let err = Arc::new(err);
+                    for handler in Self::notifiers(&routes) {
+                        handler.send(Notification::ApiError(err.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Looks up the [Route] of client `id`
+    fn route(routes: &Routes, id: i32) -> Option<Route> {
+        // NOTE: safe to unwrap because poison cleared
+        while routes.is_poisoned() {
+            routes.clear_poison();
+        }
+        routes.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Removes tasks whose request has gone away without cleaning up after itself, i.e. tasks that
+    /// are past their deadline or whose receiver has been dropped. Every request holds a
+    /// [TaskGuard], so this only catches requests whose future has been leaked.
+    fn sweep(routes: &Routes) {
+        let now = Instant::now();
+        let tasks = {
+            // NOTE: safe to unwrap because poison cleared
+            while routes.is_poisoned() {
+                routes.clear_poison();
+            }
+            routes
+                .lock()
+                .unwrap()
+                .values()
+                .map(|route| route.tasks.clone())
+                .collect::<Vec<_>>()
+        };
+
+        for tasks in tasks {
+            // NOTE: safe to unwrap because poison cleared
+            while tasks.is_poisoned() {
+                tasks.clear_poison();
+            }
+            tasks
+                .lock()
+                .unwrap()
+                .retain(|_, task| task.deadline > now && !task.sender.is_closed());
+        }
+    }
+
+    /// Collects the notification handlers of all clients
+    fn notifiers(routes: &Routes) -> Vec<NotificationSink> {
+        // NOTE: safe to unwrap because poison cleared
+        while routes.is_poisoned() {
+            routes.clear_poison();
+        }
+        routes
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|route| route.notifier.clone())
+            .collect()
+    }
+
+    /// Retrieve and notify the [TasksSync]
+    fn notify_task(tasks: &TasksSync, task_id: u64, payload: Value) -> Result<(), Value> {
+        let task_id = task_id as usize;
+
+        // Retrieve oneshot sender into hashmap
+        // NOTE: safe to unwrap because poison cleared
+        while tasks.is_poisoned() {
+            tasks.clear_poison();
+        }
+
+        // Send value to task, handing back the caller's own `@extra`
+        match tasks.lock().unwrap().remove(&task_id) {
+            Some(Task { sender, extra, .. }) => {
+                let mut payload = payload;
+                match extra {
+                    Some(extra) => payload["@extra"] = extra,
+                    None => {
+                        payload
+                            .as_object_mut()
+                            .map(|payload| payload.remove("@extra"));
+                    }
+                }
+                sender.send(payload)
+            }
+            None => Err(payload),
+        }
+    }
+
+    /// Deserializes an unsolicited payload into an [Update], applies it to the [State] and hands it
+    /// to the subscribers and the notification handler. Payloads that are not an [Update] go to
+    /// the notification handler as they are.
+    fn notify_update(
+        client_id: i32,
+        updates: &UpdateFeed,
+        notifier: &Option<NotificationSink>,
+        state: &Option<State>,
+        payload: Value,
+    ) {
+        match Update::deserialize(&payload) {
+            Result::Ok(update) => {
+                if let Some(state) = state {
+                    state.apply(&update);
+                }
+
+                let kind = payload["@type"].as_str().unwrap_or_default();
+                telemetry::update(client_id, kind);
+                let event = Event {
+                    kind: kind.into(),
+                    update: Arc::new(update),
+                };
+
+                if let Some(handler) = notifier {
+                    handler.send(Notification::Update(event.update.clone()));
+                }
+
+                // NOTE: Sender failure means that there are no subscribers right now
+                let _ = updates.send(event);
+            }
+            Err(_) => {
+                if let Some(handler) = notifier {
+                    handler.send(Notification::Driver(payload));
+                }
+            }
+        }
+    }
+
+    /// Whether `task_id` has been handed out by [TASK_ID]
+    fn issued(task_id: u64) -> bool {
+        task_id > 0 && (task_id as usize) < TASK_ID.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,102 @@
+//! # Logging
+//! TDLib's internal log is forwarded to [tracing] under the `tdlib` target, with TDLib's
+//! verbosity levels mapped onto tracing levels:
+//!
+//! | TDLib        | tracing |
+//! |--------------|---------|
+//! | 0 (fatal)    | ERROR   |
+//! | 1 (error)    | ERROR   |
+//! | 2 (warning)  | WARN    |
+//! | 3 (info)     | INFO    |
+//! | 4 (debug)    | DEBUG   |
+//! | 5+ (verbose) | TRACE   |
+//!
+//! TDLib only hands over messages up to its own verbosity level, which is set with
+//! [set_verbosity] (and per tag with [set_tag_verbosity]). By default TDLib's own log stream is
+//! disabled so that messages only arrive through [tracing]; [log_to_file] additionally writes them
+//! to a size limited file.
+//!
+//! *Note*: all of these settings are process-wide and apply to every client.
+use super::*;
+use std::ffi::CStr;
+
+/// Highest verbosity level forwarded to [tracing]
+const MAX_VERBOSITY: i32 = 1024;
+
+/// Set once the log stream has been chosen through [set_stream]
+static STREAM_SET: AtomicBool = AtomicBool::new(false);
+
+/// Registers [forward] as TDLib's log message callback and disables TDLib's own log stream,
+/// unless a log stream has been chosen already.
+pub(crate) fn install() {
+    unsafe {
+        let callback: tdlib::td_log_message_callback_ptr = Some(forward);
+        tdlib::td_set_log_message_callback(MAX_VERBOSITY, callback);
+    };
+
+    if !STREAM_SET.load(Ordering::Acquire) {
+        // NOTE: setting the log stream only fails on an invalid file path
+        let _ = apply_stream(LogStream::logStreamEmpty);
+    }
+}
+
+/// Log message callback handed to TDLib; forwards each message as a [tracing] event
+pub(crate) unsafe extern "C" fn forward(
+    verbosity_level: ::std::os::raw::c_int,
+    message: *const ::std::os::raw::c_char,
+) {
+    if message.is_null() {
+        return;
+    }
+    let message = CStr::from_ptr(message).to_string_lossy();
+    let message = message.trim_end();
+
+    match verbosity_level {
+        i32::MIN..=1 => tracing::error!(target: "tdlib", verbosity_level, "{message}"),
+        2 => tracing::warn!(target: "tdlib", verbosity_level, "{message}"),
+        3 => tracing::info!(target: "tdlib", verbosity_level, "{message}"),
+        4 => tracing::debug!(target: "tdlib", verbosity_level, "{message}"),
+        _ => tracing::trace!(target: "tdlib", verbosity_level, "{message}"),
+    }
+}
+
+/// Sets TDLib's log verbosity: 0 for fatal errors only, up to 1023 for everything.
+pub fn set_verbosity(level: i32) -> Result<(), Failure> {
+    Executor.setLogVerbosityLevel(level)?;
+    Result::Ok(())
+}
+
+/// Returns TDLib's current log verbosity
+pub fn verbosity() -> Result<i32, Failure> {
+    let LogVerbosityLevel::logVerbosityLevel(logVerbosityLevel { verbosity_level }) =
+        Executor.getLogVerbosityLevel()?;
+    Result::Ok(verbosity_level)
+}
+
+/// Sets the verbosity of a single TDLib log tag, e.g. `td_requests`.
+pub fn set_tag_verbosity(tag: &str, level: i32) -> Result<(), Failure> {
+    Executor.setLogTagVerbosityLevel(tag.to_string(), level)?;
+    Result::Ok(())
+}
+
+/// Sets where TDLib writes its own log. Messages are forwarded to [tracing] regardless.
+pub fn set_stream(stream: LogStream) -> Result<(), Failure> {
+    apply_stream(stream)?;
+    STREAM_SET.store(true, Ordering::Release);
+    Result::Ok(())
+}
+
+fn apply_stream(stream: LogStream) -> Result<(), Failure> {
+    Executor.setLogStream(stream)?;
+    Result::Ok(())
+}
+
+/// Has TDLib write its own log to the file at `path`, rotating it once it grows beyond
+/// `max_file_size` bytes.
+pub fn log_to_file<Path: Into<String>>(path: Path, max_file_size: i64) -> Result<(), Failure> {
+    set_stream(LogStream::logStreamFile(logStreamFile {
+        path: path.into(),
+        max_file_size,
+        redirect_stderr: false,
+    }))
+}
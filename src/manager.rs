@@ -0,0 +1,174 @@
+use super::*;
+#[cfg(feature = "tdjson")]
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Poll interval of the process-wide receive loop
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "tdjson")]
+/// The process-wide [ClientManager]
+static MANAGER: OnceLock<ClientManager> = OnceLock::new();
+
+/// Everything the receive loop needs to deliver a payload to one client
+#[derive(Clone)]
+pub(crate) struct Route {
+    /// Requests of the client waiting for a response
+    pub(crate) tasks: TasksSync,
+
+    /// Handler for payloads that are not a response to a request
+    pub(crate) notifier: Option<NotificationSink>,
+
+    /// Feed of typed updates to [Subscription]s
+    pub(crate) updates: UpdateFeed,
+
+    /// Store that every update is applied to first
+    pub(crate) state: Option<State>,
+}
+
+/// Routing table of the receive loop, keyed by `@client_id`
+pub(crate) type Routes = Arc<Mutex<HashMap<i32, Route>>>;
+
+/// # Client Manager
+/// `td_receive` is process-global: every payload for every client comes out of the same call, and
+/// two loops polling it steal each other's responses and updates. The [ClientManager] owns the one
+/// receive loop of the process, demultiplexes the output on `@client_id` and hands out [Driver]s,
+/// each of which is a lightweight handle to a single TDLib client with its own task queue and
+/// notification handler.
+/// - [ClientManager::global]: the process-wide manager, on the linked `libtdjson`
+/// - [ClientManager::new]: a manager on any other [Transport], e.g. a [MemoryTransport]
+/// - [ClientManager::client]: creates a new td client and returns a [Driver] for it
+///
+/// *Note*: [Driver::new] is a shorthand for `ClientManager::global().client(notifier)`, and
+/// [DriverBuilder] registers its clients with the global manager unless told otherwise.
+/// # Example
+/// ```ignore
+/// let manager = ClientManager::global();
+///
+/// let (sender, mut updates) = tokio::sync::mpsc::unbounded_channel();
+/// let first = manager.client(Some(sender));
+/// let second = manager.client(None);
+///
+/// // Responses are delivered to the driver that sent the request
+/// let (first, second) = tokio::join!(first.getAuthorizationState(), second.getAuthorizationState());
+/// ```
+#[derive(Clone)]
+pub struct ClientManager {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    routes: Routes,
+
+    /// Interface to TDLib shared by all clients of the manager
+    transport: Arc<dyn Transport>,
+
+    /// Receiver thread; stopped when the last handle to the manager is dropped
+    listener: Listener,
+}
+
+impl ClientManager {
+    #[cfg(feature = "tdjson")]
+    /// Returns the process-wide [ClientManager], starting its receive loop on first use.
+    pub fn global() -> ClientManager {
+        MANAGER
+            .get_or_init(|| {
+                // Forward tdlib's log to [tracing]
+                logging::install();
+
+                Self::new(Arc::new(Tdjson))
+            })
+            .clone()
+    }
+
+    /// # New
+    /// Creates a manager on `transport` and starts its receive loop. The receive loop stops once
+    /// the manager and all of its [Driver]s have been dropped.
+    ///
+    /// *Note*: `td_receive` is process-global, so use [ClientManager::global] rather than a second
+    /// manager on [Tdjson].
+    pub fn new(transport: Arc<dyn Transport>) -> ClientManager {
+        let routes: Routes = Arc::new(Mutex::new(HashMap::new()));
+        let listener = Listener::spawn(POLL_INTERVAL, routes.clone(), transport.clone());
+
+        ClientManager {
+            shared: Arc::new(Shared {
+                routes,
+                transport,
+                listener,
+            }),
+        }
+    }
+
+    /// Sets how long the receive loop blocks in `td_receive` before checking for a stop signal.
+    /// Takes effect from the next poll onwards.
+    pub fn set_poll_interval(&self, poll_interval: Duration) {
+        self.shared.listener.set_timeout(poll_interval);
+    }
+
+    /// # Client
+    /// Creates a new td client with the default settings and registers it with the receive loop.
+    /// Use a [DriverBuilder] for anything else.
+    /// Arguments:
+    /// - `notifier`: Option of a notification handler to call; this is the update stream of the
+    ///   new client only
+    pub fn client(&self, notifier: Option<Notifier>) -> Driver {
+        self.client_with(notifier.map(NotificationSink::from), Config::default())
+    }
+
+    /// Creates a new td client with `config` and registers it with the receive loop
+    pub(crate) fn client_with(&self, notifier: Option<NotificationSink>, config: Config) -> Driver {
+        // Create a client
+        let id = self.transport().create_client();
+
+        // Register the client before any request can be sent from it
+        let tasks = Arc::new(Mutex::new(HashMap::new()));
+        let (updates, _) = tokio::sync::broadcast::channel(config.update_capacity);
+        self.routes().insert(
+            id,
+            Route {
+                tasks: tasks.clone(),
+                notifier,
+                updates: updates.clone(),
+                state: config.state.clone(),
+            },
+        );
+
+        Driver::from_parts(id, self.clone(), tasks, updates, config)
+    }
+
+    /// Number of clients currently registered with the receive loop
+    pub fn clients(&self) -> usize {
+        self.routes().len()
+    }
+
+    /// Removes the client with `id` from the receive loop. Payloads that still arrive for it are
+    /// discarded.
+    pub(crate) fn deregister(&self, id: i32) {
+        self.routes().remove(&id);
+    }
+
+    /// Interface to TDLib of the manager
+    pub(crate) fn transport(&self) -> &dyn Transport {
+        self.shared.transport.as_ref()
+    }
+
+    /// Locks the routing table
+    fn routes(&self) -> std::sync::MutexGuard<'_, HashMap<i32, Route>> {
+        // NOTE: safe to unwrap because poison cleared
+        while self.shared.routes.is_poisoned() {
+            self.shared.routes.clear_poison();
+        }
+        self.shared.routes.lock().unwrap()
+    }
+}
+
+impl SyncApi for ClientManager {
+    /// Executes the `payload` synchronously on the [Transport] of the manager
+    fn execute<Payload: Serialize, Target: DeserializeOwned>(
+        &self,
+        payload: Payload,
+    ) -> Result<Target, Failure> {
+        transport::execute(self.transport(), payload)
+    }
+}
@@ -0,0 +1,162 @@
+use super::*;
+use std::time::Instant;
+
+#[allow(async_fn_in_trait)]
+/// # Middleware
+/// Intercepts every request made through an [Api]: the generated methods all funnel into
+/// [Api::send], so a [Layered] implementor sees each of them as a JSON `payload` on its way to
+/// `next` and the JSON response on its way back. A middleware may inspect or rewrite either,
+/// answer without calling `next` at all (e.g. from a cache), or call it several times (e.g. to
+/// retry).
+/// # Example
+/// ```ignore
+/// struct Silent;
+///
+/// impl Middleware for Silent {
+///     async fn call<Next: Api>(&self, mut payload: Value, next: &Next) -> Result<Value, Failure> {
+///         if payload["@type"] == "sendMessage" {
+///             payload["options"]["disable_notification"] = true.into();
+///         }
+///         next.send(payload).await
+///     }
+/// }
+///
+/// let api = (&driver).layer(Silent).layer(Trace);
+/// api.sendMessage(chat_id, 0, None, None, None, content).await?;
+/// ```
+pub trait Middleware {
+    /// Handles the `payload` of a request, usually by passing it on to `next`
+    async fn call<Next: Api>(&self, payload: Value, next: &Next) -> Result<Value, Failure>;
+}
+
+/// # Layered
+/// An [Api] implementor wrapped in a [Middleware], created by [ApiExt::layer]. Layers stack:
+/// the layer added last sees a request first and its response last.
+pub struct Layered<Inner: Api, Layer: Middleware> {
+    inner: Inner,
+    layer: Layer,
+}
+
+impl<Inner: Api, Layer: Middleware> Layered<Inner, Layer> {
+    /// Wraps `inner` in `layer`
+    pub fn new(inner: Inner, layer: Layer) -> Self {
+        Self { inner, layer }
+    }
+
+    /// The wrapped [Api] implementor
+    pub fn inner(&self) -> &Inner {
+        &self.inner
+    }
+}
+
+impl<Inner: Api, Layer: Middleware> Api for Layered<Inner, Layer> {
+    async fn send<Payload: Serialize, Target: DeserializeOwned>(
+        &self,
+        payload: Payload,
+    ) -> Result<Target, Failure> {
+        let payload = serde_json::to_value(payload)?;
+        let response = self.layer.call(payload, &self.inner).await?;
+        Result::Ok(serde_json::from_value(response)?)
+    }
+}
+
+impl<Inner: Api> Api for &Inner {
+    async fn send<Payload: Serialize, Target: DeserializeOwned>(
+        &self,
+        payload: Payload,
+    ) -> Result<Target, Failure> {
+        (*self).send(payload).await
+    }
+}
+
+/// Wraps any [Api] implementor in [Middleware]
+pub trait ApiExt: Api + Sized {
+    /// Wraps this in `layer`, see [Layered]
+    fn layer<Layer: Middleware>(self, layer: Layer) -> Layered<Self, Layer> {
+        Layered::new(self, layer)
+    }
+}
+
+impl<Inner: Api> ApiExt for Inner {}
+
+/// # Trace
+/// [Middleware] that logs every request as a [tracing] event at debug level, and every failure at
+/// warn level, along with how long it took
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Trace;
+
+impl Middleware for Trace {
+    async fn call<Next: Api>(&self, payload: Value, next: &Next) -> Result<Value, Failure> {
+        let method = payload["@type"].as_str().unwrap_or_default().to_string();
+        let start = Instant::now();
+
+        let response = next.send::<_, Value>(payload).await;
+        let elapsed = start.elapsed();
+        match &response {
+            Result::Ok(_) => tracing::debug!(method, ?elapsed, "request succeeded"),
+            Err(failure) => tracing::warn!(method, ?elapsed, %failure, "request failed"),
+        }
+
+        response
+    }
+}
+
+/// # Map Request
+/// [Middleware] that rewrites the payload of every request before passing it on
+/// # Example
+/// ```ignore
+/// let api = (&driver).layer(MapRequest(|payload: &mut Value| {
+///     if payload["@type"] == "sendMessage" {
+///         payload["options"]["disable_notification"] = true.into();
+///     }
+/// }));
+/// ```
+pub struct MapRequest<Map: Fn(&mut Value)>(pub Map);
+
+impl<Map: Fn(&mut Value)> Middleware for MapRequest<Map> {
+    async fn call<Next: Api>(&self, mut payload: Value, next: &Next) -> Result<Value, Failure> {
+        (self.0)(&mut payload);
+        next.send(payload).await
+    }
+}
+
+#[tokio::test]
+/// Layers see requests outermost first and can rewrite or answer them
+async fn middleware() {
+    /// Answers `getOption` from memory after the first time
+    #[derive(Default)]
+    struct Cache(Mutex<HashMap<String, Value>>);
+
+    impl Middleware for Cache {
+        async fn call<Next: Api>(&self, payload: Value, next: &Next) -> Result<Value, Failure> {
+            let key = payload.to_string();
+            if let Some(response) = self.0.lock().unwrap().get(&key) {
+                return Result::Ok(response.clone());
+            }
+            let response = next.send::<_, Value>(payload).await?;
+            self.0.lock().unwrap().insert(key, response.clone());
+            Result::Ok(response)
+        }
+    }
+
+    let transport = MemoryTransport::new();
+    transport.respond_with(|request| {
+        Some(serde_json::json!({ "@type": "optionValueString", "value": request["name"] }))
+    });
+    let driver = ClientManager::new(transport.clone()).client(None);
+
+    // The mapping runs first, so the cache sees the rewritten request
+    let api = (&driver)
+        .layer(Trace)
+        .layer(Cache::default())
+        .layer(MapRequest(|payload: &mut Value| {
+            payload["name"] = "version".into();
+        }));
+
+    let first = api.getOption("anything".into()).await.unwrap();
+    let second = api.getOption("something else".into()).await.unwrap();
+    assert_eq!(String::from(first), "version");
+    assert_eq!(String::from(second), "version");
+    assert_eq!(transport.requests().len(), 1);
+    assert_eq!(transport.requests()[0].1["name"], "version");
+}
@@ -0,0 +1,383 @@
+use super::*;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU64;
+use std::sync::Condvar;
+use std::sync::MutexGuard;
+
+/// What a [BoundedNotifier] does with a notification once its queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Block the receive loop until the receiver makes room.
+    ///
+    /// *Note*: the receive loop is shared by all clients of the [ClientManager], so a slow
+    /// receiver holds up the responses and updates of every one of them.
+    Block,
+
+    /// Discard the oldest queued notification to make room
+    DropOldest,
+
+    /// Discard the new notification
+    DropNewest,
+}
+
+/// Key of the [BoundedNotifier::coalesce] rule an update falls under, e.g. its file id
+type Coalesce = Arc<dyn Fn(&Update) -> Option<i64> + Send + Sync>;
+
+/// State shared by both ends of a bounded notification channel
+struct Channel {
+    queue: Mutex<Queue>,
+    /// Signalled whenever a notification is taken off the queue or the receiver goes away
+    space: Condvar,
+    /// Signalled whenever a notification is queued or the last sender goes away
+    ready: tokio::sync::Notify,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+struct Queue {
+    /// Queued notifications, with the coalescing key of those that have one
+    notifications: VecDeque<(Option<(usize, i64)>, Notification)>,
+    senders: usize,
+    receiver: bool,
+}
+
+impl Channel {
+    /// Locks the queue
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        // NOTE: safe to unwrap because poison cleared
+        while self.queue.is_poisoned() {
+            self.queue.clear_poison();
+        }
+        self.queue.lock().unwrap()
+    }
+}
+
+/// # Bounded
+/// Creates a notification channel that holds at most `capacity` notifications and deals with any
+/// beyond that according to `overflow`. Hand the [BoundedNotifier] to
+/// [DriverBuilder::notifier] in place of an unbounded [Notifier].
+/// # Example
+/// ```ignore
+/// let (notifier, mut notifications) = notifier::bounded(1024, Overflow::DropOldest);
+/// let notifier = notifier.coalesce(|update| match update {
+///     Update::updateFile(update) => Some(update.file.id.into()),
+///     _ => None,
+/// });
+///
+/// let driver = DriverBuilder::new().notifier(notifier).build().await?;
+/// while let Some(notification) = notifications.recv().await {
+///     println!("{notification:#?}");
+/// }
+/// ```
+pub fn bounded(capacity: usize, overflow: Overflow) -> (BoundedNotifier, NotificationReceiver) {
+    let channel = Arc::new(Channel {
+        queue: Mutex::new(Queue {
+            notifications: VecDeque::new(),
+            senders: 1,
+            receiver: true,
+        }),
+        space: Condvar::new(),
+        ready: tokio::sync::Notify::new(),
+        capacity: capacity.max(1),
+        overflow,
+        dropped: AtomicU64::new(0),
+        coalesced: AtomicU64::new(0),
+    });
+
+    let notifier = BoundedNotifier {
+        channel: channel.clone(),
+        coalesce: Arc::new(Vec::new()),
+    };
+    (notifier, NotificationReceiver { channel })
+}
+
+/// # Bounded Notifier
+/// Sending half of a channel created by [bounded]
+pub struct BoundedNotifier {
+    channel: Arc<Channel>,
+    coalesce: Arc<Vec<Coalesce>>,
+}
+
+impl BoundedNotifier {
+    /// # Coalesce
+    /// Keeps only the latest of the queued updates for which `key` returns the same key: a new
+    /// update replaces the queued one in its place in the queue rather than taking up room of its
+    /// own. Rules are independent of each other, so each can be keyed on its own id space.
+    ///
+    /// *Note*: rules apply to notifications sent after they have been added.
+    pub fn coalesce<Key: Fn(&Update) -> Option<i64> + Send + Sync + 'static>(
+        mut self,
+        key: Key,
+    ) -> Self {
+        let mut coalesce = self.coalesce.as_ref().clone();
+        coalesce.push(Arc::new(key));
+        self.coalesce = Arc::new(coalesce);
+        self
+    }
+
+    /// Number of notifications discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.channel.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of updates replaced by a later one, see [BoundedNotifier::coalesce]
+    pub fn coalesced(&self) -> u64 {
+        self.channel.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// # Send
+    /// Queues the `notification`, or deals with it according to the [Overflow] policy if the
+    /// queue is full. Notifications are discarded once the receiver has gone away.
+    pub fn send(&self, notification: Notification) {
+        let key = match &notification {
+            Notification::Update(update) => self
+                .coalesce
+                .iter()
+                .enumerate()
+                .find_map(|(rule, key)| Some((rule, key(update)?))),
+            _ => None,
+        };
+
+        let channel = &self.channel;
+        let mut queue = channel.queue();
+        if !queue.receiver {
+            return;
+        }
+
+        // Replace the queued update with the same key
+        if let Some(key) = key {
+            let queued = queue
+                .notifications
+                .iter_mut()
+                .find(|(queued, _)| *queued == Some(key));
+            if let Some((_, queued)) = queued {
+                *queued = notification;
+                channel.coalesced.fetch_add(1, Ordering::Relaxed);
+                telemetry::notification_dropped("coalesced");
+                return;
+            }
+        }
+
+        if queue.notifications.len() >= channel.capacity {
+            match channel.overflow {
+                Overflow::Block => {
+                    queue = channel
+                        .space
+                        .wait_while(queue, |queue| {
+                            queue.receiver && queue.notifications.len() >= channel.capacity
+                        })
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                    if !queue.receiver {
+                        return;
+                    }
+                }
+                Overflow::DropOldest => {
+                    queue.notifications.pop_front();
+                    channel.dropped.fetch_add(1, Ordering::Relaxed);
+                    telemetry::notification_dropped("overflow");
+                }
+                Overflow::DropNewest => {
+                    channel.dropped.fetch_add(1, Ordering::Relaxed);
+                    telemetry::notification_dropped("overflow");
+                    return;
+                }
+            }
+        }
+
+        queue.notifications.push_back((key, notification));
+        channel.ready.notify_one();
+    }
+}
+
+impl Clone for BoundedNotifier {
+    fn clone(&self) -> Self {
+        self.channel.queue().senders += 1;
+        Self {
+            channel: self.channel.clone(),
+            coalesce: self.coalesce.clone(),
+        }
+    }
+}
+
+impl Drop for BoundedNotifier {
+    fn drop(&mut self) {
+        let mut queue = self.channel.queue();
+        queue.senders -= 1;
+        if queue.senders == 0 {
+            self.channel.ready.notify_one();
+        }
+    }
+}
+
+/// # Notification Receiver
+/// Receiving half of a channel created by [bounded]
+pub struct NotificationReceiver {
+    channel: Arc<Channel>,
+}
+
+impl NotificationReceiver {
+    /// Waits for the next notification; [None] once the queue is empty and every
+    /// [BoundedNotifier] has been dropped, i.e. once the client is gone
+    pub async fn recv(&mut self) -> Option<Notification> {
+        loop {
+            {
+                let mut queue = self.channel.queue();
+                if let Some((_, notification)) = queue.notifications.pop_front() {
+                    self.channel.space.notify_all();
+                    return Some(notification);
+                }
+                if queue.senders == 0 {
+                    return None;
+                }
+            }
+            self.channel.ready.notified().await;
+        }
+    }
+
+    /// Takes the next notification off the queue without waiting
+    pub fn try_recv(&mut self) -> Option<Notification> {
+        let (_, notification) = self.channel.queue().notifications.pop_front()?;
+        self.channel.space.notify_all();
+        Some(notification)
+    }
+
+    /// Number of notifications currently queued
+    pub fn len(&self) -> usize {
+        self.channel.queue().notifications.len()
+    }
+
+    /// Whether no notifications are queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of notifications discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.channel.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of updates replaced by a later one, see [BoundedNotifier::coalesce]
+    pub fn coalesced(&self) -> u64 {
+        self.channel.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// Turns the receiver into a [futures::Stream] of notifications
+    pub fn into_stream(self) -> impl futures::Stream<Item = Notification> {
+        futures::stream::unfold(self, |mut receiver| async move {
+            let notification = receiver.recv().await?;
+            Some((notification, receiver))
+        })
+    }
+}
+
+impl Drop for NotificationReceiver {
+    fn drop(&mut self) {
+        let mut queue = self.channel.queue();
+        queue.receiver = false;
+        queue.notifications.clear();
+        self.channel.space.notify_all();
+    }
+}
+
+/// # Notification Sink
+/// Where the receive loop hands the notifications of a client: an unbounded [Notifier] or a
+/// [BoundedNotifier]. Both convert into it, see [DriverBuilder::notifier].
+#[derive(Clone)]
+pub enum NotificationSink {
+    Unbounded(Notifier),
+    Bounded(BoundedNotifier),
+}
+
+impl NotificationSink {
+    /// Hands the `notification` over to the consumer
+    pub(crate) fn send(&self, notification: Notification) {
+        match self {
+            // NOTE: Sender failure means that the receiver has dropped, we do not need to parse
+            // that event.
+            Self::Unbounded(notifier) => {
+                let _ = notifier.send(notification);
+            }
+            Self::Bounded(notifier) => notifier.send(notification),
+        }
+    }
+}
+
+impl From<Notifier> for NotificationSink {
+    fn from(value: Notifier) -> Self {
+        Self::Unbounded(value)
+    }
+}
+
+impl From<BoundedNotifier> for NotificationSink {
+    fn from(value: BoundedNotifier) -> Self {
+        Self::Bounded(value)
+    }
+}
+
+#[test]
+/// A full queue blocks or drops according to its policy, and coalesced updates replace each other
+fn bounded_notifier() {
+    use crate::file_watch::file_json;
+    use crate::file_watch::Transferred;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let driver = |n: u64| Notification::Driver(Value::from(n));
+    let file = |id: i32, size: i64| {
+        let file = file_json(id, size, Transferred::idle(0), Transferred::idle(0));
+        let update = serde_json::json!({ "@type": "updateFile", "file": file });
+        Notification::Update(Arc::new(serde_json::from_value(update).unwrap()))
+    };
+    let received = |receiver: &mut NotificationReceiver| {
+        std::iter::from_fn(|| receiver.try_recv())
+            .map(|notification| match notification {
+                Notification::Driver(value) => value.as_i64().unwrap(),
+                Notification::Update(update) => match update.as_ref() {
+                    Update::updateFile(update) => update.file.size,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Drop oldest
+    let (notifier, mut receiver) = bounded(2, Overflow::DropOldest);
+    (1..=3).for_each(|n| notifier.send(driver(n)));
+    assert_eq!(received(&mut receiver), [2, 3]);
+    assert_eq!(receiver.dropped(), 1);
+
+    // Drop newest
+    let (notifier, mut receiver) = bounded(2, Overflow::DropNewest);
+    (1..=3).for_each(|n| notifier.send(driver(n)));
+    assert_eq!(received(&mut receiver), [1, 2]);
+    assert_eq!(notifier.dropped(), 1);
+
+    // Coalesce per file id
+    let (notifier, mut receiver) = bounded(2, Overflow::DropNewest);
+    let notifier = notifier.coalesce(|update| match update {
+        Update::updateFile(update) => Some(update.file.id.into()),
+        _ => None,
+    });
+    notifier.send(file(1, 10));
+    notifier.send(file(2, 20));
+    notifier.send(file(1, 11));
+    assert_eq!(received(&mut receiver), [11, 20]);
+    assert_eq!((receiver.coalesced(), receiver.dropped()), (1, 0));
+
+    // Block until the receiver makes room
+    let (notifier, mut receiver) = bounded(1, Overflow::Block);
+    let sender = std::thread::spawn(move || (1..=3).for_each(|n| notifier.send(driver(n))));
+    let received = runtime.block_on(async {
+        let mut received = Vec::new();
+        while let Some(Notification::Driver(value)) = receiver.recv().await {
+            received.push(value.as_i64().unwrap());
+        }
+        received
+    });
+    sender.join().unwrap();
+    assert_eq!(received, [1, 2, 3]);
+}
@@ -0,0 +1,452 @@
+use super::*;
+use futures::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+
+/// Number of results asked for per request when nothing else has been configured
+pub const DEFAULT_PAGE_SIZE: i32 = 100;
+
+/// # Paging
+/// How a paginated [Stream] requests its pages:
+/// - [Paging::page_size]: number of results asked for per request; TDLib may return fewer
+/// - [Paging::limit]: total number of results after which the stream ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paging {
+    page_size: i32,
+    limit: Option<usize>,
+}
+
+impl Default for Paging {
+    fn default() -> Self {
+        Self {
+            page_size: DEFAULT_PAGE_SIZE,
+            limit: None,
+        }
+    }
+}
+
+impl Paging {
+    /// Pages of [DEFAULT_PAGE_SIZE] results until the results are exhausted
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of results asked for per request
+    pub fn page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Ends the stream after `limit` results
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+/// One page of results, see [paginate]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<Item, Cursor> {
+    pub items: Vec<Item>,
+
+    /// Cursor of the next page; [None] once the results are exhausted
+    pub next: Option<Cursor>,
+}
+
+/// State of a [paginate] stream
+struct Pager<Item, Cursor, Fetch> {
+    fetch: Fetch,
+    paging: Paging,
+    /// Cursor of the next page; [None] once the results are exhausted or a request failed
+    cursor: Option<Cursor>,
+    /// Results fetched but not yet handed out
+    buffer: VecDeque<Item>,
+    /// Number of results handed out
+    yielded: usize,
+}
+
+/// # Paginate
+/// Turns any cursor-based TDLib method into a [Stream] of its results. `fetch` is called with the
+/// cursor and the number of results to ask for, and returns a [Page]; the first call gets
+/// `cursor`. The next page is only requested once the previous one has been consumed, so a slow
+/// consumer holds the requests back. The stream ends once a page has no `next` cursor, the
+/// [Paging::limit] is reached, or after the first failure, which is handed out as the last item.
+/// # Example
+/// ```ignore
+/// let members = paginate(0, Paging::new(), |offset, limit| async move {
+///     let ChatMembers::chatMembers(members) = driver
+///         .getSupergroupMembers(supergroup_id, None, offset, limit)
+///         .await?;
+///     let next = offset + members.members.len() as i32;
+///     Ok(Page {
+///         next: (next < members.total_count).then_some(next),
+///         items: members.members,
+///     })
+/// });
+/// ```
+pub fn paginate<Item, Cursor, Fetch, Fetched>(
+    cursor: Cursor,
+    paging: Paging,
+    fetch: Fetch,
+) -> impl Stream<Item = Result<Item, Failure>>
+where
+    Fetch: FnMut(Cursor, i32) -> Fetched,
+    Fetched: Future<Output = Result<Page<Item, Cursor>, Failure>>,
+{
+    let pager = Pager {
+        fetch,
+        paging,
+        cursor: Some(cursor),
+        buffer: VecDeque::new(),
+        yielded: 0,
+    };
+
+    futures::stream::unfold(pager, |mut pager| async move {
+        loop {
+            let remaining = pager.paging.limit.map(|limit| limit - pager.yielded);
+            if remaining == Some(0) {
+                return None;
+            }
+
+            if let Some(item) = pager.buffer.pop_front() {
+                pager.yielded += 1;
+                return Some((Result::Ok(item), pager));
+            }
+
+            let cursor = pager.cursor.take()?;
+            let limit = match remaining {
+                Some(remaining) => pager
+                    .paging
+                    .page_size
+                    .min(i32::try_from(remaining).unwrap_or(i32::MAX)),
+                None => pager.paging.page_size,
+            };
+            match (pager.fetch)(cursor, limit).await {
+                // NOTE: an empty page ends the stream even with a cursor, or it would never end
+                Result::Ok(Page { items, next }) => {
+                    if !items.is_empty() {
+                        pager.cursor = next;
+                    }
+                    pager.buffer.extend(items);
+                }
+                Err(failure) => return Some((Err(failure), pager)),
+            }
+        }
+    })
+}
+
+/// # Paginated
+/// [Stream]s over the paginated methods of any [Api] implementor, built on [paginate]. Each of
+/// them pages until TDLib has no more results, working around TDLib returning fewer results
+/// than asked for: only an empty page or an exhausted cursor ends the stream.
+/// # Example
+/// ```ignore
+/// let mut history = pin!(driver.chat_history(chat_id, 0, Paging::new().limit(1000)));
+/// while let Some(message) = history.try_next().await? {
+///     println!("{}", message.id);
+/// }
+/// ```
+pub trait Paginated: Api + Sized {
+    /// Messages of a chat from `from_message_id` backwards, newest first; 0 starts from the
+    /// last message. See [Api::getChatHistory].
+    fn chat_history(
+        &self,
+        chat_id: i64,
+        from_message_id: i64,
+        paging: Paging,
+    ) -> impl Stream<Item = Result<message, Failure>> + '_ {
+        paginate(
+            from_message_id,
+            paging,
+            move |from_message_id, limit| async move {
+                let Messages::messages(messages) = self
+                    .getChatHistory(chat_id, from_message_id, 0, limit, false)
+                    .await?;
+                let items = messages.messages.unwrap_or_default();
+                Result::Ok(Page {
+                    next: items.last().map(|message| message.id),
+                    items,
+                })
+            },
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// Messages of a chat that match a search, newest first. See [Api::searchChatMessages].
+    fn search_chat_messages(
+        &self,
+        chat_id: i64,
+        query: String,
+        sender_id: Option<MessageSender>,
+        filter: Option<SearchMessagesFilter>,
+        message_thread_id: i64,
+        saved_messages_topic_id: i64,
+        paging: Paging,
+    ) -> impl Stream<Item = Result<message, Failure>> + '_ {
+        paginate(0, paging, move |from_message_id, limit| {
+            let (query, sender_id, filter) = (query.clone(), sender_id.clone(), filter.clone());
+            async move {
+                let FoundChatMessages::foundChatMessages(found) = self
+                    .searchChatMessages(
+                        chat_id,
+                        query,
+                        sender_id,
+                        from_message_id,
+                        0,
+                        limit,
+                        filter,
+                        message_thread_id,
+                        saved_messages_topic_id,
+                    )
+                    .await?;
+                Result::Ok(Page {
+                    next: (found.next_from_message_id != 0).then_some(found.next_from_message_id),
+                    items: found.messages,
+                })
+            }
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// Messages of all chats in `chat_list` that match a search, newest first. See
+    /// [Api::searchMessages].
+    fn search_messages(
+        &self,
+        chat_list: Option<ChatList>,
+        only_in_channels: bool,
+        query: String,
+        filter: Option<SearchMessagesFilter>,
+        min_date: i32,
+        max_date: i32,
+        paging: Paging,
+    ) -> impl Stream<Item = Result<message, Failure>> + '_ {
+        paginate(String::new(), paging, move |offset, limit| {
+            let (chat_list, query, filter) = (chat_list.clone(), query.clone(), filter.clone());
+            async move {
+                let FoundMessages::foundMessages(found) = self
+                    .searchMessages(
+                        chat_list,
+                        only_in_channels,
+                        query,
+                        offset,
+                        limit,
+                        filter,
+                        min_date,
+                        max_date,
+                    )
+                    .await?;
+                Result::Ok(Page {
+                    next: (!found.next_offset.is_empty()).then_some(found.next_offset),
+                    items: found.messages,
+                })
+            }
+        })
+    }
+
+    /// Members of a supergroup or channel. See [Api::getSupergroupMembers].
+    fn supergroup_members(
+        &self,
+        supergroup_id: i64,
+        filter: Option<SupergroupMembersFilter>,
+        paging: Paging,
+    ) -> impl Stream<Item = Result<chatMember, Failure>> + '_ {
+        paginate(0, paging, move |offset, limit| {
+            let filter = filter.clone();
+            async move {
+                let ChatMembers::chatMembers(members) = self
+                    .getSupergroupMembers(supergroup_id, filter, offset, limit)
+                    .await?;
+                let next = offset + members.members.len() as i32;
+                Result::Ok(Page {
+                    next: (next < members.total_count).then_some(next),
+                    items: members.members,
+                })
+            }
+        })
+    }
+
+    /// Events of the event log of a supergroup or channel, newest first. See
+    /// [Api::getChatEventLog].
+    fn chat_event_log(
+        &self,
+        chat_id: i64,
+        query: String,
+        filters: Option<chatEventLogFilters>,
+        user_ids: Vec<i64>,
+        paging: Paging,
+    ) -> impl Stream<Item = Result<chatEvent, Failure>> + '_ {
+        paginate(0, paging, move |from_event_id, limit| {
+            let (query, filters, user_ids) = (query.clone(), filters.clone(), user_ids.clone());
+            async move {
+                let ChatEvents::chatEvents(events) = self
+                    .getChatEventLog(chat_id, query, from_event_id, limit, filters, user_ids)
+                    .await?;
+                Result::Ok(Page {
+                    next: events.events.last().map(|event| event.id),
+                    items: events.events,
+                })
+            }
+        })
+    }
+
+    /// Ids of the chats in `chat_list`, in order, loading more of them with [Api::loadChats] as
+    /// the stream is consumed
+    fn chats(
+        &self,
+        chat_list: Option<ChatList>,
+        paging: Paging,
+    ) -> impl Stream<Item = Result<i64, Failure>> + '_ {
+        paginate(0usize, paging, move |loaded, limit| {
+            let chat_list = chat_list.clone();
+            async move {
+                loop {
+                    // NOTE: TDLib answers 404 once every chat of the list has been loaded
+                    let exhausted = match self.loadChats(chat_list.clone(), limit).await {
+                        Result::Ok(_) => false,
+                        Err(failure) if failure.kind() == ErrorKind::NotFound => true,
+                        Err(failure) => return Err(failure),
+                    };
+
+                    let wanted = (loaded as i32).saturating_add(limit);
+                    let Chats::chats(chats) = self.getChats(chat_list.clone(), wanted).await?;
+                    let items: Vec<_> = chats.chat_ids.into_iter().skip(loaded).collect();
+
+                    // A load that brought nothing new does not mean that the list is exhausted
+                    if items.is_empty() && !exhausted {
+                        continue;
+                    }
+
+                    let next = loaded + items.len();
+                    return Result::Ok(Page {
+                        next: (!exhausted || next < chats.total_count as usize).then_some(next),
+                        items,
+                    });
+                }
+            }
+        })
+    }
+}
+
+impl<Inner: Api> Paginated for Inner {}
+
+#[tokio::test]
+/// Pages are requested until exhausted, even when TDLib returns fewer results than asked for
+async fn paginated() {
+    use futures::StreamExt;
+    use futures::TryStreamExt;
+
+    // 7 members, served at most 3 at a time
+    let transport = MemoryTransport::new();
+    transport.respond_with(|request| {
+        let offset = request["offset"].as_i64()?;
+        let limit = request["limit"].as_i64()?.min(3);
+        let members: Vec<_> = (offset..(offset + limit).min(7))
+            .map(|user_id| {
+                serde_json::json!({
+                    "@type": "chatMember",
+                    "member_id": { "@type": "messageSenderUser", "user_id": user_id },
+                    "inviter_user_id": 0,
+                    "joined_chat_date": 0,
+                    "status": { "@type": "chatMemberStatusMember", "member_until_date": 0 },
+                })
+            })
+            .collect();
+        Some(serde_json::json!({ "@type": "chatMembers", "total_count": 7, "members": members }))
+    });
+    let driver = ClientManager::new(transport.clone()).client(None);
+    let user_id = |member: chatMember| match member.member_id {
+        MessageSender::messageSenderUser(sender) => sender.user_id,
+        MessageSender::messageSenderChat(sender) => sender.chat_id,
+    };
+
+    let members: Vec<_> = driver
+        .supergroup_members(1, None, Paging::new())
+        .map_ok(user_id)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(members, [0, 1, 2, 3, 4, 5, 6]);
+    assert_eq!(transport.requests().len(), 3);
+
+    // The last request only asks for what is left of the limit
+    let members: Vec<_> = driver
+        .supergroup_members(1, None, Paging::new().page_size(2).limit(5))
+        .map_ok(user_id)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(members, [0, 1, 2, 3, 4]);
+    let limits: Vec<_> = transport.requests()[3..]
+        .iter()
+        .map(|(_, request)| request["limit"].as_i64().unwrap())
+        .collect();
+    assert_eq!(limits, [2, 2, 1]);
+
+    // A limit beyond `i32::MAX` does not wrap around
+    let members: Vec<_> = driver
+        .supergroup_members(1, None, Paging::new().page_size(3).limit(usize::MAX))
+        .map_ok(user_id)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(members.len(), 7);
+    let limits: Vec<_> = transport.requests()[6..]
+        .iter()
+        .map(|(_, request)| request["limit"].as_i64().unwrap())
+        .collect();
+    assert_eq!(limits, [3, 3, 3]);
+
+    // A failure ends the stream
+    let pages = paginate(0, Paging::new(), |page: i32, _| async move {
+        match page {
+            0 => Result::Ok(Page {
+                items: vec![page],
+                next: Some(1),
+            }),
+            _ => Err(Failure::Receiver(ReceiverError::Empty)),
+        }
+    });
+    let pages: Vec<_> = pages.collect().await;
+    assert!(matches!(pages.as_slice(), [Result::Ok(0), Err(_)]));
+}
+
+#[tokio::test]
+/// Chats are loaded until TDLib has none left, even if a load brings nothing new
+async fn paginated_chats() {
+    use futures::TryStreamExt;
+
+    // Every load adds the next batch of chats; the second one adds nothing
+    let batches = [vec![1, 2], vec![], vec![3]];
+    let loaded = Arc::new(Mutex::new((0, Vec::new())));
+    let transport = MemoryTransport::new();
+    transport.respond_with(move |request| {
+        let mut loaded = loaded.lock().unwrap();
+        match request["@type"].as_str()? {
+            "loadChats" => match batches.get(loaded.0) {
+                Some(batch) => {
+                    loaded.0 += 1;
+                    loaded.1.extend(batch);
+                    Some(serde_json::json!({ "@type": "ok" }))
+                }
+                None => Some(serde_json::json!({ "@type": "error", "code": 404, "message": "" })),
+            },
+            "getChats" => {
+                let limit = request["limit"].as_u64()? as usize;
+                let chat_ids: Vec<i64> = loaded.1.iter().copied().take(limit).collect();
+                let total_count = loaded.1.len();
+                Some(serde_json::json!({
+                    "@type": "chats", "total_count": total_count, "chat_ids": chat_ids
+                }))
+            }
+            _ => None,
+        }
+    });
+    let driver = ClientManager::new(transport.clone()).client(None);
+
+    let chats: Vec<_> = driver
+        .chats(None, Paging::new().page_size(2))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(chats, [1, 2, 3]);
+}
@@ -0,0 +1,592 @@
+use super::*;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Most bytes of data a callback button can carry
+pub const CALLBACK_DATA_LIMIT: usize = 64;
+
+/// How long a handler of a callback or inline query may take before it is answered with an empty
+/// response; Telegram gives up waiting for an answer shortly after
+pub const DEFAULT_ANSWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+/// Errors of encoding the data of a callback button
+pub enum CallbackDataError {
+    /// The encoded data is longer than [CALLBACK_DATA_LIMIT] bytes
+    TooLong(usize),
+
+    /// The key contains a `:`, which separates it from the payload
+    InvalidKey(String),
+
+    /// The payload could not be serialized
+    Serde(serde_json::Error),
+}
+
+impl std::fmt::Display for CallbackDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLong(len) => write!(
+                f,
+                "callback data is {len} bytes long, at most {CALLBACK_DATA_LIMIT} are allowed"
+            ),
+            Self::InvalidKey(key) => write!(f, "callback key {key:?} contains ':'"),
+            Self::Serde(err) => write!(f, "invalid callback payload: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CallbackDataError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::TooLong(_) | Self::InvalidKey(_) => None,
+            Self::Serde(err) => Some(err),
+        }
+    }
+}
+
+/// # Callback Data
+/// Encodes `payload` as the data of a callback button, routed to the handler registered for `key`
+/// with [Dispatcher::callback]. The data is `key:` followed by the payload as JSON, so keep both
+/// short to stay within [CALLBACK_DATA_LIMIT]. The key itself may not contain a `:`.
+///
+/// *Note*: TDLib passes data as base64, which is what this returns.
+pub fn callback_data<Payload: Serialize>(
+    key: &str,
+    payload: &Payload,
+) -> Result<String, CallbackDataError> {
+    if key.contains(':') {
+        return Err(CallbackDataError::InvalidKey(key.to_string()));
+    }
+    let payload = serde_json::to_string(payload).map_err(CallbackDataError::Serde)?;
+    let data = format!("{key}:{payload}");
+    if data.len() > CALLBACK_DATA_LIMIT {
+        return Err(CallbackDataError::TooLong(data.len()));
+    }
+    Result::Ok(STANDARD.encode(data))
+}
+
+/// # Callback Button
+/// Inline keyboard button with `text` that sends `payload` to the handler registered for `key`
+/// # Example
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Vote { poll: i64, yes: bool }
+///
+/// let markup = ReplyMarkup::replyMarkupInlineKeyboard(replyMarkupInlineKeyboard {
+///     rows: vec![vec![
+///         callback_button("Yes", "vote", &Vote { poll, yes: true })?,
+///         callback_button("No", "vote", &Vote { poll, yes: false })?,
+///     ]],
+/// });
+/// ```
+pub fn callback_button<Text: Into<String>, Payload: Serialize>(
+    text: Text,
+    key: &str,
+    payload: &Payload,
+) -> Result<inlineKeyboardButton, CallbackDataError> {
+    Result::Ok(inlineKeyboardButton {
+        text: text.into(),
+        r#type: InlineKeyboardButtonType::inlineKeyboardButtonTypeCallback(
+            inlineKeyboardButtonTypeCallback {
+                data: callback_data(key, payload)?,
+            },
+        ),
+    })
+}
+
+/// Splits base64 callback data into its key and JSON payload; [None] if it was not encoded by
+/// [callback_data]
+pub(crate) fn decode_callback_data(data: &str) -> Option<(String, String)> {
+    let data = String::from_utf8(STANDARD.decode(data).ok()?).ok()?;
+    let (key, payload) = data.split_once(':')?;
+    Some((key.to_string(), payload.to_string()))
+}
+
+/// Decodes the JSON payload of callback data
+pub(crate) fn decode_payload<Payload: DeserializeOwned>(payload: &str) -> Result<Payload, Failure> {
+    Result::Ok(serde_json::from_str(payload)?)
+}
+
+/// # Callback Context
+/// What a callback query handler of a [Dispatcher] is called with
+pub struct CallbackContext<State, Payload> {
+    /// The driver the query was received through
+    pub driver: Arc<Driver>,
+
+    /// Shared state of the [Dispatcher]
+    pub state: Arc<State>,
+
+    /// The callback query
+    pub query: updateNewCallbackQuery,
+
+    /// The payload of the pressed button
+    pub payload: Payload,
+}
+
+/// # Callback Answer
+/// Answer to a callback query, sent with `answerCallbackQuery`. The default answer shows nothing
+/// and only stops the loading indicator of the button.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallbackAnswer {
+    text: String,
+    show_alert: bool,
+    url: String,
+    cache_time: i32,
+}
+
+impl CallbackAnswer {
+    /// Answer that shows nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows `text` as a notification at the top of the chat
+    pub fn text<Text: Into<String>>(mut self, text: Text) -> Self {
+        self.text = text.into();
+        self
+    }
+
+    /// Shows the text as an alert instead of a notification
+    pub fn alert(mut self) -> Self {
+        self.show_alert = true;
+        self
+    }
+
+    /// Opens `url`, e.g. a game or a `t.me/bot?start=` link
+    pub fn url<Url: Into<String>>(mut self, url: Url) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Lets clients cache the answer for `cache_time` seconds
+    pub fn cache_time(mut self, cache_time: i32) -> Self {
+        self.cache_time = cache_time;
+        self
+    }
+
+    /// Sends the answer to the query with `query_id`
+    pub(crate) async fn send(self, driver: &Driver, query_id: i64) -> Result<(), Failure> {
+        driver
+            .answerCallbackQuery(
+                query_id,
+                self.text,
+                self.show_alert,
+                self.url,
+                self.cache_time,
+            )
+            .await?;
+        Result::Ok(())
+    }
+}
+
+/// # Inline Context
+/// What the inline query handler of a [Dispatcher] is called with
+pub struct InlineContext<State> {
+    /// The driver the query was received through
+    pub driver: Arc<Driver>,
+
+    /// Shared state of the [Dispatcher]
+    pub state: Arc<State>,
+
+    /// The inline query
+    pub query: updateNewInlineQuery,
+}
+
+/// # Inline Answer
+/// Answer to an inline query, sent with `answerInlineQuery`. The default answer has no results.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InlineAnswer {
+    results: Vec<InputInlineQueryResult>,
+    is_personal: bool,
+    button: Option<inlineQueryResultsButton>,
+    cache_time: i32,
+    next_offset: String,
+}
+
+impl InlineAnswer {
+    /// Answer with `results`, e.g. [InlineResult]s
+    pub fn new<Item: Into<InputInlineQueryResult>>(
+        results: impl IntoIterator<Item = Item>,
+    ) -> Self {
+        Self {
+            results: results.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Caches the results only for the user that sent the query
+    pub fn personal(mut self) -> Self {
+        self.is_personal = true;
+        self
+    }
+
+    /// Shows `button` above the results
+    pub fn button(mut self, button: inlineQueryResultsButton) -> Self {
+        self.button = Some(button);
+        self
+    }
+
+    /// Lets the results be cached for `cache_time` seconds
+    pub fn cache_time(mut self, cache_time: i32) -> Self {
+        self.cache_time = cache_time;
+        self
+    }
+
+    /// Offset the client sends with the query for the next results; no more results if empty
+    pub fn next_offset<Offset: Into<String>>(mut self, next_offset: Offset) -> Self {
+        self.next_offset = next_offset.into();
+        self
+    }
+
+    /// Sends the answer to the query with `query_id`
+    pub(crate) async fn send(self, driver: &Driver, query_id: i64) -> Result<(), Failure> {
+        driver
+            .answerInlineQuery(
+                query_id,
+                self.is_personal,
+                self.button,
+                self.results,
+                self.cache_time,
+                self.next_offset,
+            )
+            .await?;
+        Result::Ok(())
+    }
+}
+
+/// Kind of an [InlineResult] and the fields only that kind has
+#[derive(Debug, Clone, PartialEq)]
+enum ResultKind {
+    Article {
+        url: String,
+    },
+    Photo {
+        photo_url: String,
+    },
+    Document {
+        document_url: String,
+        mime_type: String,
+    },
+    Location {
+        location: location,
+    },
+}
+
+/// # Inline Result
+/// Builder of the common [InputInlineQueryResult]s, which converts into one
+/// - [InlineResult::article]: a message with a title
+/// - [InlineResult::photo]: a JPEG photo at a URL
+/// - [InlineResult::document]: a PDF or ZIP file at a URL
+/// - [InlineResult::location]: a point on the map
+/// # Example
+/// ```ignore
+/// let results = [InlineResult::article("1", "Hello", content).description("Say hello")];
+/// Ok(InlineAnswer::new(results).personal())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineResult {
+    id: String,
+    title: String,
+    description: String,
+    thumbnail: (String, i32, i32),
+    reply_markup: Option<ReplyMarkup>,
+    content: InputMessageContent,
+    kind: ResultKind,
+}
+
+impl InlineResult {
+    fn new(id: String, title: String, content: InputMessageContent, kind: ResultKind) -> Self {
+        Self {
+            id,
+            title,
+            description: String::new(),
+            thumbnail: (String::new(), 0, 0),
+            reply_markup: None,
+            content,
+            kind,
+        }
+    }
+
+    /// Result titled `title` that sends `content`
+    pub fn article<Id: Into<String>, Title: Into<String>>(
+        id: Id,
+        title: Title,
+        content: InputMessageContent,
+    ) -> Self {
+        let kind = ResultKind::Article { url: String::new() };
+        Self::new(id.into(), title.into(), content, kind)
+    }
+
+    /// Result showing the JPEG photo at `photo_url` that sends `content`; `inputMessagePhoto` sends
+    /// the photo itself
+    pub fn photo<Id: Into<String>, Url: Into<String>>(
+        id: Id,
+        photo_url: Url,
+        content: InputMessageContent,
+    ) -> Self {
+        let photo_url = photo_url.into();
+        let kind = ResultKind::Photo {
+            photo_url: photo_url.clone(),
+        };
+        let mut result = Self::new(id.into(), String::new(), content, kind);
+        result.thumbnail.0 = photo_url;
+        result
+    }
+
+    /// Result showing the file at `document_url` that sends `content`; `inputMessageDocument` sends
+    /// the file itself
+    pub fn document<Id: Into<String>, Title: Into<String>, Url: Into<String>>(
+        id: Id,
+        title: Title,
+        document_url: Url,
+        mime_type: &str,
+        content: InputMessageContent,
+    ) -> Self {
+        let kind = ResultKind::Document {
+            document_url: document_url.into(),
+            mime_type: mime_type.into(),
+        };
+        Self::new(id.into(), title.into(), content, kind)
+    }
+
+    /// Result showing `location` that sends `content`; `inputMessageLocation` sends the location
+    /// itself
+    pub fn location<Id: Into<String>, Title: Into<String>>(
+        id: Id,
+        title: Title,
+        location: location,
+        content: InputMessageContent,
+    ) -> Self {
+        let kind = ResultKind::Location { location };
+        Self::new(id.into(), title.into(), content, kind)
+    }
+
+    /// Title of the result
+    pub fn title<Title: Into<String>>(mut self, title: Title) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Short description shown below the title; not shown for locations
+    pub fn description<Description: Into<String>>(mut self, description: Description) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Thumbnail at `url` with its size in pixels, 0 if unknown
+    pub fn thumbnail<Url: Into<String>>(mut self, url: Url, width: i32, height: i32) -> Self {
+        self.thumbnail = (url.into(), width, height);
+        self
+    }
+
+    /// Inline keyboard attached to the sent message, e.g. of [callback_button]s
+    pub fn keyboard(mut self, rows: Vec<Vec<inlineKeyboardButton>>) -> Self {
+        self.reply_markup = Some(ReplyMarkup::replyMarkupInlineKeyboard(
+            replyMarkupInlineKeyboard { rows },
+        ));
+        self
+    }
+
+    /// URL of an article, shown with it
+    pub fn url<Url: Into<String>>(mut self, url: Url) -> Self {
+        if let ResultKind::Article { url: article_url } = &mut self.kind {
+            *article_url = url.into();
+        }
+        self
+    }
+}
+
+impl From<InlineResult> for InputInlineQueryResult {
+    fn from(value: InlineResult) -> Self {
+        let InlineResult {
+            id,
+            title,
+            description,
+            thumbnail: (thumbnail_url, thumbnail_width, thumbnail_height),
+            reply_markup,
+            content: input_message_content,
+            kind,
+        } = value;
+
+        match kind {
+            ResultKind::Article { url } => {
+                Self::inputInlineQueryResultArticle(inputInlineQueryResultArticle {
+                    id,
+                    hide_url: url.is_empty(),
+                    url,
+                    title,
+                    description,
+                    thumbnail_url,
+                    thumbnail_width,
+                    thumbnail_height,
+                    reply_markup,
+                    input_message_content,
+                })
+            }
+            ResultKind::Photo { photo_url } => {
+                Self::inputInlineQueryResultPhoto(inputInlineQueryResultPhoto {
+                    id,
+                    title,
+                    description,
+                    thumbnail_url,
+                    photo_url,
+                    photo_width: 0,
+                    photo_height: 0,
+                    reply_markup,
+                    input_message_content,
+                })
+            }
+            ResultKind::Document {
+                document_url,
+                mime_type,
+            } => Self::inputInlineQueryResultDocument(inputInlineQueryResultDocument {
+                id,
+                title,
+                description,
+                document_url,
+                mime_type,
+                thumbnail_url,
+                thumbnail_width,
+                thumbnail_height,
+                reply_markup,
+                input_message_content,
+            }),
+            ResultKind::Location { location } => {
+                Self::inputInlineQueryResultLocation(inputInlineQueryResultLocation {
+                    id,
+                    location,
+                    live_period: 0,
+                    title,
+                    thumbnail_url,
+                    thumbnail_width,
+                    thumbnail_height,
+                    reply_markup,
+                    input_message_content,
+                })
+            }
+        }
+    }
+}
+
+#[tokio::test]
+/// Queries are answered with what their handler returns, or emptily if it fails or is too slow
+async fn queries() {
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Vote {
+        poll: i64,
+        yes: bool,
+    }
+
+    let transport = MemoryTransport::new();
+    transport.respond_with(|_| Some(serde_json::json!({ "@type": "ok" })));
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+
+    let dispatcher = Dispatcher::new(())
+        .answer_timeout(Duration::from_millis(100))
+        .callback("vote", |context: CallbackContext<(), Vote>| async move {
+            match context.payload {
+                Vote { yes: true, poll } => {
+                    Result::Ok(CallbackAnswer::new().text(poll.to_string()))
+                }
+                Vote { yes: false, .. } => Err(Failure::Receiver(ReceiverError::Empty)),
+            }
+        })
+        .callback("slow", |_: CallbackContext<(), ()>| async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Result::Ok(CallbackAnswer::new().text("too late"))
+        })
+        .inline_query(|context: InlineContext<()>| async move {
+            let content = InputMessageContent::inputMessageText(inputMessageText {
+                text: formattedText {
+                    text: context.query.query.clone(),
+                    entities: vec![],
+                },
+                link_preview_options: None,
+                clear_draft: false,
+            });
+            let result = InlineResult::article("1", "Echo", content).description("Echo the query");
+            Result::Ok(InlineAnswer::new([result]).personal())
+        });
+    tokio::spawn(dispatcher.run(driver.clone()));
+    tokio::task::yield_now().await;
+
+    // Payloads are limited in size
+    assert!(callback_data("vote", &Vote { poll: 1, yes: true }).is_ok());
+    assert!(matches!(
+        callback_data("vote", &"x".repeat(64)),
+        Err(CallbackDataError::TooLong(_))
+    ));
+    assert!(matches!(
+        callback_data("vote:yes", &()),
+        Err(CallbackDataError::InvalidKey(key)) if key == "vote:yes"
+    ));
+
+    let callback = |id: i64, data: String| {
+        serde_json::json!({
+            "@type": "updateNewCallbackQuery", "id": id, "sender_user_id": 1, "chat_id": 1,
+            "message_id": 1, "chat_instance": 0,
+            "payload": { "@type": "callbackQueryPayloadData", "data": data },
+        })
+    };
+    let queries = [
+        callback(
+            1,
+            callback_data("vote", &Vote { poll: 7, yes: true }).unwrap(),
+        ),
+        callback(
+            2,
+            callback_data(
+                "vote",
+                &Vote {
+                    poll: 7,
+                    yes: false,
+                },
+            )
+            .unwrap(),
+        ),
+        callback(3, callback_data("slow", &()).unwrap()),
+        callback(4, callback_data("unknown", &()).unwrap()),
+        serde_json::json!({
+            "@type": "updateNewInlineQuery", "id": 5, "sender_user_id": 1,
+            "query": "hello", "offset": "",
+        }),
+    ];
+    for query in queries {
+        transport.push(driver.id, query);
+    }
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while transport.requests().len() < 4 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let answers: HashMap<_, _> = transport
+        .requests()
+        .into_iter()
+        .map(|(_, request)| {
+            let id = request["callback_query_id"]
+                .as_i64()
+                .or(request["inline_query_id"].as_i64())
+                .unwrap();
+            (id, request)
+        })
+        .collect();
+    assert_eq!(answers[&1]["text"], "7");
+    assert_eq!(answers[&2]["text"], "");
+    assert_eq!(answers[&3]["text"], "");
+    assert!(!answers.contains_key(&4));
+    assert_eq!(answers[&5]["is_personal"], true);
+    assert_eq!(
+        answers[&5]["results"][0]["input_message_content"]["text"]["text"],
+        "hello"
+    );
+}
@@ -0,0 +1,359 @@
+use super::*;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Number of idle per-chat buckets kept before they are pruned
+const CHAT_BUCKETS: usize = 1024;
+
+/// # Rate Limit
+/// At most `count` requests per `period`, with bursts of up to `count` requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    count: u32,
+    period: Duration,
+}
+
+impl RateLimit {
+    /// At most `count` requests per `period`
+    pub fn new(count: u32, period: Duration) -> Self {
+        Self {
+            count: count.max(1),
+            period,
+        }
+    }
+
+    /// At most `count` requests per second
+    pub fn per_second(count: u32) -> Self {
+        Self::new(count, Duration::from_secs(1))
+    }
+
+    /// At most `count` requests per minute
+    pub fn per_minute(count: u32) -> Self {
+        Self::new(count, Duration::from_secs(60))
+    }
+}
+
+/// Token bucket of a [RateLimit]
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// A full bucket
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.count as f64,
+            updated: now,
+        }
+    }
+
+    /// Adds the tokens that have accrued since the last refill
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let accrued =
+            elapsed.as_secs_f64() * self.limit.count as f64 / self.limit.period.as_secs_f64();
+        self.tokens = (self.tokens + accrued).min(self.limit.count as f64);
+        self.updated = now;
+    }
+
+    /// How long until a token is available
+    fn wait(&self) -> Duration {
+        let missing = 1.0 - self.tokens;
+        match missing > 0.0 {
+            true => self.limit.period.mul_f64(missing / self.limit.count as f64),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// Whether the bucket has been idle long enough to be full again
+    fn full(&self) -> bool {
+        self.tokens >= self.limit.count as f64
+    }
+}
+
+/// Bucket of a chat, with the queue of requests waiting for it
+#[derive(Debug)]
+struct Chat {
+    bucket: Bucket,
+    /// Held by the request at the head of the queue, so the requests to a chat go out in order
+    queue: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    global: Option<Bucket>,
+    methods: HashMap<String, Bucket>,
+    chats: HashMap<i64, Chat>,
+}
+
+/// # Rate Limiter
+/// Token buckets that hold requests back until they fit into the configured [RateLimit]s:
+/// - [RateLimiter::global]: every request
+/// - [RateLimiter::method]: every request to one method, keyed on the `@type` of the payload
+/// - [RateLimiter::per_chat]: every request to one chat, keyed on the `chat_id` of the payload
+///
+/// A request is only sent once all buckets that apply to it have a token, so it may wait, but it
+/// never fails because of a limit. Requests to the same chat are sent in the order they were made.
+///
+/// Clones share their buckets, so one limiter can be handed to several [Driver]s, e.g. all clients
+/// of one bot.
+///
+/// *Note*: a [Driver] is not limited unless it is built with a limiter, see
+/// [DriverBuilder::rate_limit]. The time spent waiting does not count towards the timeout of the
+/// request.
+/// # Example
+/// ```ignore
+/// let driver = DriverBuilder::new()
+///     .rate_limit(
+///         RateLimiter::new()
+///             .global(RateLimit::per_second(30))
+///             .per_chat(RateLimit::per_second(1))
+///             .only(["sendMessage", "forwardMessages", "editMessageText"]),
+///     )
+///     .build()
+///     .await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    global: Option<RateLimit>,
+    methods: HashMap<String, RateLimit>,
+    per_chat: Option<RateLimit>,
+    only: Option<HashSet<String>>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    /// Limiter without any limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits all requests together
+    pub fn global(mut self, limit: RateLimit) -> Self {
+        self.global = Some(limit);
+        self
+    }
+
+    /// Limits the requests to `method`, on top of the global limit
+    pub fn method<Method: Into<String>>(mut self, method: Method, limit: RateLimit) -> Self {
+        self.methods.insert(method.into(), limit);
+        self
+    }
+
+    /// Limits the requests to each chat separately, on top of the global limit
+    pub fn per_chat(mut self, limit: RateLimit) -> Self {
+        self.per_chat = Some(limit);
+        self
+    }
+
+    /// Only limits requests to `methods`; by default every request is limited
+    pub fn only<Method: Into<String>>(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.only = Some(methods.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Locks the buckets
+    fn buckets(&self) -> std::sync::MutexGuard<'_, Buckets> {
+        // NOTE: safe to unwrap because poison cleared
+        while self.buckets.is_poisoned() {
+            self.buckets.clear_poison();
+        }
+        self.buckets.lock().unwrap()
+    }
+
+    /// # Acquire
+    /// Waits until the `payload` fits into every limit that applies to it, and takes a token from
+    /// each of them
+    pub async fn acquire(&self, payload: &Value) {
+        let method = payload["@type"].as_str().unwrap_or_default();
+        if self
+            .only
+            .as_ref()
+            .is_some_and(|only| !only.contains(method))
+        {
+            return;
+        }
+        let method = self.methods.get_key_value(method);
+        let chat_id = self.per_chat.and(payload["chat_id"].as_i64());
+
+        // Queue up behind earlier requests to the same chat
+        let _queue = match chat_id {
+            Some(chat_id) => Some(self.queue(chat_id).lock_owned().await),
+            None => None,
+        };
+
+        loop {
+            let wait = {
+                let now = Instant::now();
+                let mut buckets = self.buckets();
+                let Buckets {
+                    global,
+                    methods,
+                    chats,
+                } = &mut *buckets;
+
+                let global = self
+                    .global
+                    .map(|limit| global.get_or_insert_with(|| Bucket::new(limit, now)));
+                let method = method.map(|(method, limit)| {
+                    methods
+                        .entry(method.clone())
+                        .or_insert_with(|| Bucket::new(*limit, now))
+                });
+                let chat = chat_id
+                    .and_then(|chat_id| chats.get_mut(&chat_id))
+                    .map(|chat| &mut chat.bucket);
+
+                let mut applicable = [global, method, chat];
+                let wait = applicable
+                    .iter_mut()
+                    .flatten()
+                    .map(|bucket| {
+                        bucket.refill(now);
+                        bucket.wait()
+                    })
+                    .max()
+                    .unwrap_or_default();
+
+                if wait.is_zero() {
+                    applicable
+                        .iter_mut()
+                        .flatten()
+                        .for_each(|bucket| bucket.tokens -= 1.0);
+                    return;
+                }
+                wait
+            };
+
+            tracing::debug!(method = %payload["@type"], chat_id, ?wait, "rate limited");
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Queue of the requests to `chat_id`, whose bucket is created on first use
+    fn queue(&self, chat_id: i64) -> Arc<tokio::sync::Mutex<()>> {
+        let Some(limit) = self.per_chat else {
+            return Arc::default();
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets();
+
+        // Forget chats that nobody is waiting for and whose bucket is full again
+        if buckets.chats.len() >= CHAT_BUCKETS && !buckets.chats.contains_key(&chat_id) {
+            buckets.chats.retain(|_, chat| {
+                chat.bucket.refill(now);
+                !chat.bucket.full() || Arc::strong_count(&chat.queue) > 1
+            });
+        }
+
+        let chat = buckets.chats.entry(chat_id).or_insert_with(|| Chat {
+            bucket: Bucket::new(limit, now),
+            queue: Arc::default(),
+        });
+        chat.queue.clone()
+    }
+}
+
+impl Middleware for RateLimiter {
+    /// Holds the request back until it fits into the limits, for [Api] implementors other than a
+    /// [Driver] built with [DriverBuilder::rate_limit]
+    async fn call<Next: Api>(&self, payload: Value, next: &Next) -> Result<Value, Failure> {
+        self.acquire(&payload).await;
+        next.send(payload).await
+    }
+}
+
+#[tokio::test(start_paused = true)]
+/// Requests queue up until they fit into the global, per-method and per-chat limits
+async fn rate_limiter() {
+    /// Answers every request right away, noting when it arrived
+    #[derive(Default)]
+    struct Clock(Mutex<Vec<(Value, Duration)>>, Option<Instant>);
+
+    impl Api for Clock {
+        async fn send<Payload: Serialize, Target: DeserializeOwned>(
+            &self,
+            payload: Payload,
+        ) -> Result<Target, Failure> {
+            let elapsed = self.1.unwrap().elapsed();
+            let payload = serde_json::to_value(payload)?;
+            self.0.lock().unwrap().push((payload, elapsed));
+            Result::Ok(serde_json::from_value(
+                serde_json::json!({ "@type": "ok" }),
+            )?)
+        }
+    }
+
+    let limiter = RateLimiter::new()
+        .global(RateLimit::per_second(4))
+        .method("getMe", RateLimit::per_second(1))
+        .per_chat(RateLimit::per_second(2));
+    let api = Arc::new(Clock(Mutex::default(), Some(Instant::now())).layer(limiter));
+    let sent = |method: &str| -> Vec<Duration> {
+        let sent = api.inner().0.lock().unwrap();
+        sent.iter()
+            .filter(|(payload, _)| payload["@type"] == method)
+            .map(|(_, elapsed)| *elapsed)
+            .collect()
+    };
+
+    // Burst of the global limit, then one request every 250ms
+    for _ in 0..6 {
+        api.send::<_, Value>(serde_json::json!({ "@type": "getOption" }))
+            .await
+            .unwrap();
+    }
+    let millis = |millis: &[u64]| {
+        millis
+            .iter()
+            .map(|millis| Duration::from_millis(*millis))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(sent("getOption"), millis(&[0, 0, 0, 0, 250, 500]));
+
+    // One request per second to the method
+    for _ in 0..2 {
+        api.send::<_, Value>(serde_json::json!({ "@type": "getMe" }))
+            .await
+            .unwrap();
+    }
+    assert_eq!(sent("getMe"), millis(&[750, 1750]));
+
+    // Requests to one chat queue up in order; other chats are not held up
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let mut requests = tokio::task::JoinSet::new();
+    for (chat_id, position) in [(1, 0), (1, 1), (1, 2), (2, 0)] {
+        let api = api.clone();
+        let payload =
+            serde_json::json!({ "@type": "getChat", "chat_id": chat_id, "position": position });
+        requests.spawn(async move { api.send::<_, Value>(payload).await.unwrap() });
+    }
+    requests.join_all().await;
+
+    let sent = api.inner().0.lock().unwrap();
+    let chats: Vec<_> = sent
+        .iter()
+        .filter(|(payload, _)| payload["@type"] == "getChat")
+        .map(|(payload, elapsed)| {
+            (
+                payload["chat_id"].as_i64().unwrap(),
+                payload["position"].as_i64().unwrap(),
+                elapsed.as_millis(),
+            )
+        })
+        .collect();
+    let chat = |chat_id: i64| {
+        chats
+            .iter()
+            .filter(|chat| chat.0 == chat_id)
+            .map(|chat| (chat.1, chat.2))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(chat(1), [(0, 3750), (1, 3750), (2, 4250)]);
+    assert_eq!(chat(2), [(0, 3750)]);
+}
@@ -0,0 +1,367 @@
+use super::*;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Condvar;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Direction of a recorded payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Payload handed to `td_send`
+    Send,
+
+    /// Payload returned by `td_receive`
+    Receive,
+}
+
+/// # Record
+/// One line of a recording made by a [Recorder]
+#[derive(Debug, Clone, PartialEq, Serialize, serde::Deserialize)]
+pub struct Record {
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+
+    pub direction: Direction,
+
+    /// Client the payload was sent to or received for; [None] if the payload is not valid JSON
+    pub client_id: Option<i32>,
+
+    /// The payload; a payload that is not valid JSON is recorded as a string
+    pub payload: Value,
+}
+
+impl Record {
+    fn new(direction: Direction, client_id: Option<i32>, payload: Value) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        Self {
+            timestamp,
+            direction,
+            client_id,
+            payload,
+        }
+    }
+}
+
+/// # Recorder
+/// [Transport] that writes every payload sent and received through `inner` to a JSONL file, one
+/// [Record] per line. The recording can be fed back through a [Driver] with [Replay].
+/// # Example
+/// ```ignore
+/// let recorder = Recorder::create(Tdjson, "session.jsonl")?;
+/// let manager = ClientManager::new(Arc::new(recorder));
+///
+/// let driver = DriverBuilder::new().manager(manager).build().await?;
+/// ```
+pub struct Recorder<Inner: Transport> {
+    inner: Inner,
+    file: Mutex<std::io::BufWriter<std::fs::File>>,
+}
+
+impl<Inner: Transport> Recorder<Inner> {
+    /// Records the traffic of `inner` to the file at `path`, which is truncated
+    pub fn create<File: AsRef<Path>>(inner: Inner, path: File) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Result::Ok(Self {
+            inner,
+            file: Mutex::new(std::io::BufWriter::new(file)),
+        })
+    }
+
+    /// Appends a [Record] to the file
+    fn record(&self, record: Record) {
+        // NOTE: safe to unwrap because poison cleared
+        while self.file.is_poisoned() {
+            self.file.clear_poison();
+        }
+        let mut file = self.file.lock().unwrap();
+
+        // NOTE: a recording that cannot be written must not take the client down with it
+        if let Result::Ok(line) = serde_json::to_string(&record) {
+            let _ = writeln!(file, "{line}").and_then(|_| file.flush());
+        }
+    }
+}
+
+impl<Inner: Transport> Transport for Recorder<Inner> {
+    fn create_client(&self) -> i32 {
+        self.inner.create_client()
+    }
+
+    fn send(&self, client_id: i32, request: &str) {
+        let payload = serde_json::from_str(request).unwrap_or(Value::from(request));
+        self.record(Record::new(Direction::Send, Some(client_id), payload));
+        self.inner.send(client_id, request);
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<String> {
+        let payload = self.inner.receive(timeout)?;
+        let record = match serde_json::from_str::<Value>(&payload) {
+            Result::Ok(value) => {
+                let client_id = value["@client_id"].as_i64().map(|id| id as i32);
+                Record::new(Direction::Receive, client_id, value)
+            }
+            Err(_) => Record::new(Direction::Receive, None, Value::from(payload.as_str())),
+        };
+        self.record(record);
+        Some(payload)
+    }
+
+    fn execute(&self, request: &str) -> Option<String> {
+        self.inner.execute(request)
+    }
+}
+
+/// # Replay
+/// [Transport] that plays a recording made by a [Recorder] back to a [Driver], so that logic built
+/// on [Api] and [Subscription]s can be re-run offline against real traffic:
+/// - Clients are handed the ids of the recorded clients, in the order in which they first appear.
+/// - The n-th request a client sends is taken to be the n-th request it sent while recording; the
+///   `@extra` of the recorded response is rewritten to the `@extra` of the new request.
+/// - Received payloads are played back in the recorded order, as fast as they are consumed. A
+///   response is held back, together with everything recorded after it, until its request has
+///   been sent.
+///
+/// *Note*: replay is deterministic as long as the driver sends the same requests in the same
+/// order as it did while recording; deviations are logged. Playback starts as soon as a client is
+/// created, so updates recorded before its first request are best taken from its [Notifier].
+pub struct Replay {
+    replay: Mutex<Replaying>,
+    /// Signalled whenever a request is sent
+    ready: Condvar,
+}
+
+struct Replaying {
+    /// Recorded client ids not yet handed out
+    clients: VecDeque<i32>,
+
+    /// Last client id handed out beyond the recorded ones
+    unrecorded: i32,
+
+    /// Recorded requests not yet replayed, per client
+    requests: HashMap<i32, VecDeque<Value>>,
+
+    /// Recorded payloads not yet played back
+    queue: VecDeque<Record>,
+
+    /// Recorded `@extra` mapped to the `@extra` of the replayed request
+    extras: HashMap<(i32, u64), Value>,
+}
+
+impl Replay {
+    /// Replays the JSONL recording at `path`
+    pub fn open<File: AsRef<Path>>(path: File) -> std::io::Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let records = file
+            .lines()
+            .filter(|line| !matches!(line, Result::Ok(line) if line.trim().is_empty()))
+            .map(|line| Result::Ok(serde_json::from_str::<Record>(&line?)?))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        Result::Ok(Self::new(records))
+    }
+
+    /// Replays `records`
+    pub fn new(records: impl IntoIterator<Item = Record>) -> Self {
+        let mut clients = VecDeque::new();
+        let mut requests = HashMap::<_, VecDeque<_>>::new();
+        let mut queue = VecDeque::new();
+
+        for record in records {
+            if let Some(client_id) = record.client_id {
+                if !clients.contains(&client_id) {
+                    clients.push_back(client_id);
+                }
+            }
+
+            match (record.direction, record.client_id) {
+                (Direction::Send, Some(client_id)) => requests
+                    .entry(client_id)
+                    .or_default()
+                    .push_back(record.payload),
+                (Direction::Send, None) => (),
+                (Direction::Receive, _) => queue.push_back(record),
+            }
+        }
+
+        Self {
+            replay: Mutex::new(Replaying {
+                unrecorded: clients.iter().copied().max().unwrap_or(0),
+                clients,
+                requests,
+                queue,
+                extras: HashMap::new(),
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Locks the state of the replay
+    fn replaying(&self) -> MutexGuard<'_, Replaying> {
+        // NOTE: safe to unwrap because poison cleared
+        while self.replay.is_poisoned() {
+            self.replay.clear_poison();
+        }
+        self.replay.lock().unwrap()
+    }
+}
+
+impl Replaying {
+    /// Whether the next payload can be played back, i.e. it is not a response to a request that
+    /// has not been replayed yet
+    fn ready(&self) -> bool {
+        match self.queue.front() {
+            Some(Record {
+                client_id: Some(client_id),
+                payload,
+                ..
+            }) => match payload["@extra"].as_u64() {
+                Some(extra) => self.extras.contains_key(&(*client_id, extra)),
+                None => true,
+            },
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Takes the next payload off the queue, with its `@extra` rewritten to that of the replayed
+    /// request
+    fn next(&mut self) -> Option<String> {
+        if !self.ready() {
+            return None;
+        }
+        let Record {
+            client_id, payload, ..
+        } = self.queue.pop_front()?;
+
+        match (client_id, payload) {
+            (_, Value::String(raw)) => Some(raw),
+            (Some(client_id), mut payload) => {
+                if let Some(extra) = payload["@extra"].as_u64() {
+                    payload["@extra"] = self.extras.remove(&(client_id, extra))?;
+                }
+                Some(payload.to_string())
+            }
+            (None, payload) => Some(payload.to_string()),
+        }
+    }
+}
+
+impl Transport for Replay {
+    fn create_client(&self) -> i32 {
+        // Clients beyond those recorded get ids no recorded payload is addressed to
+        let mut replaying = self.replaying();
+        match replaying.clients.pop_front() {
+            Some(client_id) => client_id,
+            None => {
+                replaying.unrecorded += 1;
+                replaying.unrecorded
+            }
+        }
+    }
+
+    fn send(&self, client_id: i32, request: &str) {
+        let Result::Ok(request) = serde_json::from_str::<Value>(request) else {
+            return;
+        };
+
+        let mut replaying = self.replaying();
+        let recorded = replaying
+            .requests
+            .get_mut(&client_id)
+            .and_then(|requests| requests.pop_front());
+
+        match recorded {
+            Some(recorded) => {
+                if recorded["@type"] != request["@type"] {
+                    tracing::warn!(
+                        client_id,
+                        recorded = %recorded["@type"],
+                        replayed = %request["@type"],
+                        "replayed request differs from the recording"
+                    );
+                }
+                if let (Some(extra), Some(replayed)) =
+                    (recorded["@extra"].as_u64(), request.get("@extra"))
+                {
+                    replaying
+                        .extras
+                        .insert((client_id, extra), replayed.clone());
+                }
+                self.ready.notify_all();
+            }
+            None => {
+                tracing::warn!(client_id, request = %request["@type"], "request was not recorded")
+            }
+        }
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<String> {
+        let replaying = self.replaying();
+        let (mut replaying, _) = self
+            .ready
+            .wait_timeout_while(replaying, timeout, |replaying| !replaying.ready())
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        replaying.next()
+    }
+
+    fn execute(&self, _request: &str) -> Option<String> {
+        None
+    }
+}
+
+#[tokio::test]
+/// A recorded session replays to the same results, with `@extra` remapped to the new requests
+async fn record_replay() {
+    let path = std::env::temp_dir().join(format!("tdlib-driver-{}.jsonl", std::process::id()));
+
+    // Record
+    let transport = MemoryTransport::new();
+    transport.respond_with(|request| match request["@type"].as_str() {
+        Some("getOption") => Some(serde_json::json!({
+            "@type": "optionValueString",
+            "value": "1.8.0",
+        })),
+        _ => None,
+    });
+    let recorder = Recorder::create(transport.clone(), &path).unwrap();
+    let manager = ClientManager::new(Arc::new(recorder));
+    let driver = manager.client(None);
+    let mut updates = driver.subscribe();
+
+    transport.push(
+        driver.id,
+        serde_json::json!({
+            "@type": "updateOption",
+            "name": "version",
+            "value": { "@type": "optionValueString", "value": "1.8.0" },
+        }),
+    );
+    updates.recv().await.unwrap();
+    let recorded = driver.getOption("version".into()).await.unwrap();
+    std::mem::drop((driver, manager));
+
+    // Replay
+    // NOTE: playback starts as soon as the client exists, so updates are taken from the notifier
+    let replay = Replay::open(&path).unwrap();
+    let manager = ClientManager::new(Arc::new(replay));
+    let (sender, mut notifications) = tokio::sync::mpsc::unbounded_channel();
+    let driver = manager.client(Some(sender));
+
+    let notification = notifications.recv().await.unwrap();
+    assert!(matches!(
+        notification,
+        Notification::Update(update) if matches!(update.as_ref(), Update::updateOption(_))
+    ));
+    let replayed = driver.getOption("version".into()).await.unwrap();
+    assert_eq!(recorded, replayed);
+
+    let _ = std::fs::remove_file(path);
+}
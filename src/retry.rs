@@ -0,0 +1,260 @@
+use super::*;
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+/// Number of attempts of a [RetryPolicy::new]
+pub const DEFAULT_RETRY_ATTEMPTS: usize = 3;
+
+/// Longest retry-after hint a [RetryPolicy::new] waits for
+pub const DEFAULT_MAX_FLOOD_WAIT: Duration = Duration::from_secs(60);
+
+/// # Retry Policy
+/// Which failed requests a [Driver] repeats, and when:
+/// - `FLOOD_WAIT` ([ErrorKind::FloodWait]): after the retry-after hint of the server, unless the
+///   hint is longer than [RetryPolicy::max_flood_wait]
+/// - Internal errors (5xx, [ErrorKind::Internal]): after an exponential backoff with jitter
+///
+/// Any other failure is handed back right away, and so is the last failure once
+/// [RetryPolicy::max_attempts] have been made. Every retry is logged as a [tracing] warning.
+///
+/// *Note*: a [Driver] does not retry unless it is built with a policy, see [DriverBuilder::retry].
+/// # Example
+/// ```ignore
+/// let driver = DriverBuilder::new()
+///     .retry(RetryPolicy::new().max_attempts(5).no_retry("sendMessage"))
+///     .build()
+///     .await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    max_flood_wait: Duration,
+    backoff: Duration,
+    max_backoff: Duration,
+    no_retry: HashSet<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Policy of [DEFAULT_RETRY_ATTEMPTS] attempts that waits out flood waits of up to
+    /// [DEFAULT_MAX_FLOOD_WAIT] and backs off from 500ms up to 30s on internal errors
+    pub fn new() -> Self {
+        Self {
+            max_attempts: DEFAULT_RETRY_ATTEMPTS,
+            max_flood_wait: DEFAULT_MAX_FLOOD_WAIT,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            no_retry: HashSet::new(),
+        }
+    }
+
+    /// Policy that never retries
+    pub fn none() -> Self {
+        Self::new().max_attempts(1)
+    }
+
+    /// Number of attempts of a request, including the first one
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Longest retry-after hint to wait for; a flood wait with a longer hint is handed back as a
+    /// failure
+    pub fn max_flood_wait(mut self, max_flood_wait: Duration) -> Self {
+        self.max_flood_wait = max_flood_wait;
+        self
+    }
+
+    /// Backoff after the first internal error, doubling with every further attempt up to `max`
+    pub fn backoff(mut self, backoff: Duration, max: Duration) -> Self {
+        self.backoff = backoff;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Never retries requests to `method`, e.g. non-idempotent ones like `sendMessage`
+    pub fn no_retry<Method: Into<String>>(mut self, method: Method) -> Self {
+        self.no_retry.insert(method.into());
+        self
+    }
+
+    /// How long to wait before repeating a request to `method` that failed with `failure` on
+    /// attempt number `attempt`; [None] if it is not repeated
+    pub(crate) fn delay(
+        &self,
+        method: Option<&str>,
+        attempt: usize,
+        failure: &Failure,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || method.is_some_and(|m| self.no_retry.contains(m)) {
+            return None;
+        }
+
+        match failure.kind() {
+            ErrorKind::FloodWait { retry_after } => {
+                (retry_after <= self.max_flood_wait).then_some(retry_after)
+            }
+            ErrorKind::Internal => Some(self.backoff_delay(attempt)),
+            _ => None,
+        }
+    }
+
+    /// Exponential backoff after attempt number `attempt`, with a random jitter of up to half of it
+    fn backoff_delay(&self, attempt: usize) -> Duration {
+        let exponent = (attempt.saturating_sub(1)).min(16) as u32;
+        let backoff = self
+            .backoff
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_backoff);
+
+        // NOTE: hashing with a fresh random state is random enough for jitter
+        let random = std::collections::hash_map::RandomState::new().hash_one(attempt);
+        let jitter = (random % 1000) as f64 / 1000.0;
+        backoff / 2 + backoff.div_f64(2.0).mul_f64(jitter)
+    }
+}
+
+impl Driver {
+    /// Sends the `payload` with [Driver::request], repeating it as the [RetryPolicy] of the driver
+    /// allows. Every attempt waits for the [RateLimiter] of the driver, if any.
+    pub(crate) async fn retrying<Target: DeserializeOwned>(
+        &self,
+        payload: Value,
+        timeout: Duration,
+    ) -> Result<Target, Failure> {
+        let method = payload["@type"].as_str().map(str::to_string);
+
+        let mut attempt = 1;
+        loop {
+            if let Some(limiter) = &self.config.rate_limiter {
+                limiter.acquire(&payload).await;
+                self.accepting()?;
+            }
+
+            let failure = match self.request(payload.clone(), timeout).await {
+                Result::Ok(target) => return Result::Ok(target),
+                Err(failure) => failure,
+            };
+
+            let Some(delay) = self
+                .config
+                .retry
+                .delay(method.as_deref(), attempt, &failure)
+            else {
+                return Err(failure);
+            };
+            tracing::warn!(
+                client_id = self.id,
+                method,
+                attempt,
+                ?delay,
+                %failure,
+                "retrying request"
+            );
+            tokio::time::sleep(delay).await;
+
+            self.accepting()?;
+            attempt += 1;
+        }
+    }
+}
+
+impl Middleware for RetryPolicy {
+    /// Repeats the request on `next` as the policy allows, for [Api] implementors other than a
+    /// [Driver] built with [DriverBuilder::retry]
+    async fn call<Next: Api>(&self, payload: Value, next: &Next) -> Result<Value, Failure> {
+        let method = payload["@type"].as_str().map(str::to_string);
+
+        let mut attempt = 1;
+        loop {
+            let failure = match next.send(payload.clone()).await {
+                Result::Ok(response) => return Result::Ok(response),
+                Err(failure) => failure,
+            };
+
+            let Some(delay) = self.delay(method.as_deref(), attempt, &failure) else {
+                return Err(failure);
+            };
+            tracing::warn!(method, attempt, ?delay, %failure, "retrying request");
+            tokio::time::sleep(delay).await;
+
+            attempt += 1;
+        }
+    }
+}
+
+#[tokio::test]
+/// Flood waits and internal errors are retried; other errors and opted out methods are not
+async fn retry_policy() {
+    let error = |code: i32, message: &str| serde_json::json!({ "@type": "error", "code": code, "message": message });
+
+    let transport = MemoryTransport::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    transport.respond_with({
+        let calls = calls.clone();
+        move |request| {
+            let call = calls.fetch_add(1, Ordering::Relaxed);
+            Some(match (request["@type"].as_str(), call % 2) {
+                (Some("getMe"), 0) => error(429, "Too Many Requests: retry after 0"),
+                (Some("getCurrentState"), 0) => error(420, "FLOOD_WAIT_0"),
+                (Some("getOption"), 0) => error(500, "Request aborted"),
+                (Some("getUser"), 0) => error(502, "Bad Gateway"),
+                (Some("getChat"), _) => error(400, "CHAT_ID_INVALID"),
+                _ => serde_json::json!({ "@type": "optionValueEmpty" }),
+            })
+        }
+    });
+
+    let driver = DriverBuilder::new()
+        .manager(ClientManager::new(transport.clone()))
+        .retry(
+            RetryPolicy::new()
+                .backoff(Duration::from_millis(1), Duration::from_millis(10))
+                .no_retry("getMe"),
+        )
+        .build()
+        .await
+        .unwrap();
+
+    // Internal error, then success
+    calls.store(0, Ordering::Relaxed);
+    let value = driver.getOption("version".into()).await.unwrap();
+    assert_eq!(value, OptionValue::optionValueEmpty);
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // Any other 5xx, then success
+    calls.store(0, Ordering::Relaxed);
+    let payload = serde_json::json!({ "@type": "getUser", "user_id": 1 });
+    driver.send::<_, Value>(payload).await.unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // Flood wait, then success
+    calls.store(0, Ordering::Relaxed);
+    let payload = serde_json::json!({ "@type": "getCurrentState" });
+    driver.send::<_, Value>(payload).await.unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // Opted out
+    calls.store(0, Ordering::Relaxed);
+    let failure = driver
+        .send::<_, Value>(serde_json::json!({ "@type": "getMe" }))
+        .await;
+    assert!(matches!(
+        failure.unwrap_err().kind(),
+        ErrorKind::FloodWait { .. }
+    ));
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    // Not retryable
+    calls.store(0, Ordering::Relaxed);
+    let failure = driver.getChat(1).await.unwrap_err();
+    assert_eq!(failure.kind(), ErrorKind::BadRequest);
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+}
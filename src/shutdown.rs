@@ -0,0 +1,127 @@
+use super::*;
+use std::time::Duration;
+
+impl Driver {
+    /// # Shutdown
+    /// Closes the TDLib client gracefully, so that its database is not left mid-write:
+    /// 1. Stops accepting new requests; they fail with [ReceiverError::ShuttingDown].
+    /// 2. Waits for in-flight requests to complete.
+    /// 3. Sends `close` and waits for `authorizationStateClosed`.
+    /// 4. Removes the client from the receive loop.
+    ///
+    /// Arguments:
+    /// - `deadline`: upper bound on the whole sequence. Once it passes, the client is removed
+    ///   from the receive loop right away and [ReceiverError::Timeout] is returned.
+    ///
+    /// *Note*: dropping a [Driver] without calling this still sends `close`, but does not wait
+    /// for it to take effect.
+    pub async fn shutdown(&self, deadline: Duration) -> Result<(), Failure> {
+        let deadline = tokio::time::Instant::now() + deadline;
+
+        // Stop accepting new requests
+        self.closing.store(true, Ordering::Release);
+        self.closed.notify_waiters();
+
+        // Drain in-flight requests, then close the client
+        let result = tokio::time::timeout_at(deadline, async {
+            self.drained().await;
+            self.close(deadline).await
+        })
+        .await;
+
+        // Stop receiving for this client
+        self.manager.deregister(self.id);
+
+        result?
+    }
+
+    /// Waits until no request is in flight
+    async fn drained(&self) {
+        loop {
+            // NOTE: registered before checking, so that the last request cannot finish unnoticed
+            let drained = self.drained.notified();
+            tokio::pin!(drained);
+            drained.as_mut().enable();
+            if self.pending() == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+
+    /// Waits until [Driver::shutdown] has stopped accepting requests
+    pub(crate) async fn closed(&self) {
+        // NOTE: registered before checking, so that a shutdown cannot start unnoticed
+        let closed = self.closed.notified();
+        tokio::pin!(closed);
+        closed.as_mut().enable();
+        if !self.closing.load(Ordering::Acquire) {
+            closed.await;
+        }
+    }
+
+    /// Sends `close` and waits for `authorizationStateClosed`
+    async fn close(&self, deadline: tokio::time::Instant) -> Result<(), Failure> {
+        // Subscribe before asking for the state so that no transition is missed
+        let mut updates = self.subscribe_to(["updateAuthorizationState"]);
+        let timeout = deadline - tokio::time::Instant::now();
+
+        let state = self
+            .request::<_, AuthorizationState>(
+                serde_json::json!({ "@type": "getAuthorizationState" }),
+                timeout,
+            )
+            .await?;
+        if state == AuthorizationState::authorizationStateClosed {
+            return Result::Ok(());
+        }
+
+        self.request::<_, Ok>(serde_json::json!({ "@type": "close" }), timeout)
+            .await?;
+
+        while let Some(update) = updates.recv().await {
+            if let Update::updateAuthorizationState(updateAuthorizationState {
+                authorization_state: AuthorizationState::authorizationStateClosed,
+            }) = update.as_ref()
+            {
+                break;
+            }
+        }
+
+        Result::Ok(())
+    }
+}
+
+#[tokio::test]
+/// Shutting down waits for the requests in flight before it closes the client
+async fn shutdown_drains() {
+    let transport = MemoryTransport::new();
+    transport.respond_with(|request| match request["@type"].as_str()? {
+        "getAuthorizationState" => Some(serde_json::json!({ "@type": "authorizationStateClosed" })),
+        _ => None,
+    });
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+
+    // Left unanswered until it times out
+    let in_flight = tokio::spawn({
+        let driver = driver.clone();
+        async move {
+            driver
+                .with_timeout(Duration::from_millis(100))
+                .getMe()
+                .await
+        }
+    });
+    while driver.pending() == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    driver.shutdown(Duration::from_secs(5)).await.unwrap();
+    assert!(in_flight.is_finished());
+    let methods: Vec<_> = transport
+        .requests()
+        .into_iter()
+        .map(|(_, request)| request["@type"].clone())
+        .collect();
+    assert_eq!(methods, ["getMe", "getAuthorizationState"]);
+}
@@ -0,0 +1,420 @@
+use super::*;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+
+/// Everything TDLib has pushed about the entities of a client
+#[derive(Debug, Default)]
+struct Entities {
+    users: HashMap<i64, user>,
+    chats: HashMap<i64, chat>,
+    basic_groups: HashMap<i64, basicGroup>,
+    supergroups: HashMap<i64, supergroup>,
+    secret_chats: HashMap<i32, secretChat>,
+    files: HashMap<i32, file>,
+}
+
+/// # State
+/// Local copy of the users, chats, basic groups, supergroups, secret chats and files of a client,
+/// kept up to date from `updateUser`, `updateNewChat`, `updateChat*`, `updateFile` and the like.
+/// TDLib pushes every entity before it is referenced anywhere else, so this answers what would
+/// otherwise take a `getChat` or `getUser` round trip:
+/// - [State::chat], [State::user], [State::basic_group], [State::supergroup],
+///   [State::secret_chat], [State::file]: the latest known version of an entity
+/// - [State::chat_list]: the chats of a list, in the order TDLib sorts them
+///
+/// A [Driver] built with [DriverBuilder::state] applies every update to its state in the receive
+/// loop, before the update reaches any [Subscription] or notification handler. Clones share the
+/// same store.
+/// # Example
+/// ```ignore
+/// let state = State::new();
+/// let driver = DriverBuilder::new().state(state.clone()).build().await?;
+///
+/// driver.loadChats(Some(ChatList::chatListMain), 20).await?;
+/// for chat_id in state.chat_list(&ChatList::chatListMain) {
+///     println!("{}", state.chat(chat_id).unwrap().title);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    entities: Arc<RwLock<Entities>>,
+}
+
+impl State {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks the store for reading
+    fn read(&self) -> RwLockReadGuard<'_, Entities> {
+        self.entities
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Locks the store for writing
+    fn write(&self) -> RwLockWriteGuard<'_, Entities> {
+        self.entities
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The user with `user_id`
+    pub fn user(&self, user_id: i64) -> Option<user> {
+        self.read().users.get(&user_id).cloned()
+    }
+
+    /// The chat with `chat_id`
+    pub fn chat(&self, chat_id: i64) -> Option<chat> {
+        self.read().chats.get(&chat_id).cloned()
+    }
+
+    /// The basic group with `basic_group_id`
+    pub fn basic_group(&self, basic_group_id: i64) -> Option<basicGroup> {
+        self.read().basic_groups.get(&basic_group_id).cloned()
+    }
+
+    /// The supergroup or channel with `supergroup_id`
+    pub fn supergroup(&self, supergroup_id: i64) -> Option<supergroup> {
+        self.read().supergroups.get(&supergroup_id).cloned()
+    }
+
+    /// The secret chat with `secret_chat_id`
+    pub fn secret_chat(&self, secret_chat_id: i32) -> Option<secretChat> {
+        self.read().secret_chats.get(&secret_chat_id).cloned()
+    }
+
+    /// The file with `file_id`
+    pub fn file(&self, file_id: i32) -> Option<file> {
+        self.read().files.get(&file_id).cloned()
+    }
+
+    /// # Chat List
+    /// Ids of the chats in `list`, in the order TDLib sorts them: by descending position order,
+    /// ties broken by descending chat id.
+    ///
+    /// *Note*: TDLib only pushes the positions of the chats it has loaded; call `loadChats` until
+    /// it reports 404 to have all of them.
+    pub fn chat_list(&self, list: &ChatList) -> Vec<i64> {
+        let entities = self.read();
+        let mut chats: Vec<_> = entities
+            .chats
+            .values()
+            .filter_map(|chat| {
+                let position = chat.positions.iter().find(|p| &p.list == list)?;
+                Some((position.order, chat.id))
+            })
+            .collect();
+        chats.sort_unstable_by(|a, b| b.cmp(a));
+        chats.into_iter().map(|(_, chat_id)| chat_id).collect()
+    }
+
+    /// # Apply
+    /// Brings the store up to date with `update`. Updates that do not concern any of the stored
+    /// entities are ignored.
+    pub fn apply(&self, update: &Update) {
+        let mut entities = self.write();
+        let Entities {
+            users,
+            chats,
+            basic_groups,
+            supergroups,
+            secret_chats,
+            files,
+        } = &mut *entities;
+
+        // Changes the chat with `chat_id`, if TDLib has pushed it before
+        let mut chat = |chat_id: i64, change: &dyn Fn(&mut chat)| {
+            if let Some(chat) = chats.get_mut(&chat_id) {
+                change(chat);
+            }
+        };
+
+        match update {
+            Update::updateUser(update) => {
+                users.insert(update.user.id, update.user.clone());
+            }
+            Update::updateUserStatus(update) => {
+                if let Some(user) = users.get_mut(&update.user_id) {
+                    user.status = update.status.clone();
+                }
+            }
+            Update::updateBasicGroup(update) => {
+                basic_groups.insert(update.basic_group.id, update.basic_group.clone());
+            }
+            Update::updateSupergroup(update) => {
+                supergroups.insert(update.supergroup.id, update.supergroup.clone());
+            }
+            Update::updateSecretChat(update) => {
+                secret_chats.insert(update.secret_chat.id, update.secret_chat.clone());
+            }
+            Update::updateFile(update) => {
+                files.insert(update.file.id, update.file.clone());
+            }
+            Update::updateNewChat(update) => {
+                chats.insert(update.chat.id, update.chat.clone());
+            }
+            Update::updateChatTitle(update) => {
+                chat(update.chat_id, &|chat| chat.title = update.title.clone())
+            }
+            Update::updateChatPhoto(update) => {
+                chat(update.chat_id, &|chat| chat.photo = update.photo.clone())
+            }
+            Update::updateChatAccentColors(update) => chat(update.chat_id, &|chat| {
+                chat.accent_color_id = update.accent_color_id;
+                chat.background_custom_emoji_id = update.background_custom_emoji_id;
+                chat.profile_accent_color_id = update.profile_accent_color_id;
+                chat.profile_background_custom_emoji_id = update.profile_background_custom_emoji_id;
+            }),
+            Update::updateChatPermissions(update) => chat(update.chat_id, &|chat| {
+                chat.permissions = update.permissions.clone()
+            }),
+            Update::updateChatLastMessage(update) => chat(update.chat_id, &|chat| {
+                chat.last_message = update.last_message.clone();
+                chat.positions = update.positions.clone();
+            }),
+            Update::updateChatDraftMessage(update) => chat(update.chat_id, &|chat| {
+                chat.draft_message = update.draft_message.clone();
+                chat.positions = update.positions.clone();
+            }),
+            Update::updateChatPosition(update) => chat(update.chat_id, &|chat| {
+                // NOTE: an order of 0 removes the chat from the list
+                let position = &update.position;
+                chat.positions.retain(|p| p.list != position.list);
+                if position.order != 0 {
+                    chat.positions.push(position.clone());
+                }
+            }),
+            Update::updateChatAddedToList(update) => chat(update.chat_id, &|chat| {
+                if !chat.chat_lists.contains(&update.chat_list) {
+                    chat.chat_lists.push(update.chat_list.clone());
+                }
+            }),
+            Update::updateChatRemovedFromList(update) => chat(update.chat_id, &|chat| {
+                chat.chat_lists.retain(|list| list != &update.chat_list)
+            }),
+            Update::updateChatMessageSender(update) => chat(update.chat_id, &|chat| {
+                chat.message_sender_id = update.message_sender_id.clone()
+            }),
+            Update::updateChatHasProtectedContent(update) => chat(update.chat_id, &|chat| {
+                chat.has_protected_content = update.has_protected_content
+            }),
+            Update::updateChatIsTranslatable(update) => chat(update.chat_id, &|chat| {
+                chat.is_translatable = update.is_translatable
+            }),
+            Update::updateChatIsMarkedAsUnread(update) => chat(update.chat_id, &|chat| {
+                chat.is_marked_as_unread = update.is_marked_as_unread
+            }),
+            Update::updateChatViewAsTopics(update) => chat(update.chat_id, &|chat| {
+                chat.view_as_topics = update.view_as_topics
+            }),
+            Update::updateChatBlockList(update) => chat(update.chat_id, &|chat| {
+                chat.block_list = update.block_list.clone()
+            }),
+            Update::updateChatHasScheduledMessages(update) => chat(update.chat_id, &|chat| {
+                chat.has_scheduled_messages = update.has_scheduled_messages
+            }),
+            Update::updateChatDefaultDisableNotification(update) => chat(update.chat_id, &|chat| {
+                chat.default_disable_notification = update.default_disable_notification
+            }),
+            Update::updateChatReadInbox(update) => chat(update.chat_id, &|chat| {
+                chat.last_read_inbox_message_id = update.last_read_inbox_message_id;
+                chat.unread_count = update.unread_count;
+            }),
+            Update::updateChatReadOutbox(update) => chat(update.chat_id, &|chat| {
+                chat.last_read_outbox_message_id = update.last_read_outbox_message_id
+            }),
+            Update::updateChatUnreadMentionCount(update) => chat(update.chat_id, &|chat| {
+                chat.unread_mention_count = update.unread_mention_count
+            }),
+            Update::updateMessageMentionRead(update) => chat(update.chat_id, &|chat| {
+                chat.unread_mention_count = update.unread_mention_count
+            }),
+            Update::updateChatUnreadReactionCount(update) => chat(update.chat_id, &|chat| {
+                chat.unread_reaction_count = update.unread_reaction_count
+            }),
+            Update::updateMessageUnreadReactions(update) => chat(update.chat_id, &|chat| {
+                chat.unread_reaction_count = update.unread_reaction_count
+            }),
+            Update::updateChatNotificationSettings(update) => chat(update.chat_id, &|chat| {
+                chat.notification_settings = update.notification_settings.clone()
+            }),
+            Update::updateChatAvailableReactions(update) => chat(update.chat_id, &|chat| {
+                chat.available_reactions = update.available_reactions.clone()
+            }),
+            Update::updateChatMessageAutoDeleteTime(update) => chat(update.chat_id, &|chat| {
+                chat.message_auto_delete_time = update.message_auto_delete_time
+            }),
+            Update::updateChatEmojiStatus(update) => chat(update.chat_id, &|chat| {
+                chat.emoji_status = update.emoji_status.clone()
+            }),
+            Update::updateChatBackground(update) => chat(update.chat_id, &|chat| {
+                chat.background = update.background.clone()
+            }),
+            Update::updateChatTheme(update) => chat(update.chat_id, &|chat| {
+                chat.theme_name = update.theme_name.clone()
+            }),
+            Update::updateChatActionBar(update) => chat(update.chat_id, &|chat| {
+                chat.action_bar = update.action_bar.clone()
+            }),
+            Update::updateChatBusinessBotManageBar(update) => chat(update.chat_id, &|chat| {
+                chat.business_bot_manage_bar = update.business_bot_manage_bar.clone()
+            }),
+            Update::updateChatVideoChat(update) => chat(update.chat_id, &|chat| {
+                chat.video_chat = update.video_chat.clone()
+            }),
+            Update::updateChatPendingJoinRequests(update) => chat(update.chat_id, &|chat| {
+                chat.pending_join_requests = update.pending_join_requests.clone()
+            }),
+            Update::updateChatReplyMarkup(update) => chat(update.chat_id, &|chat| {
+                chat.reply_markup_message_id = update.reply_markup_message_id
+            }),
+            _ => (),
+        }
+    }
+}
+
+#[tokio::test]
+/// Chats are stored from `updateNewChat`, changed by `updateChat*` and listed in order
+async fn state() {
+    let chat = |id: i64, title: &str| {
+        let flags = |names: &[&str]| {
+            let flags = names
+                .iter()
+                .map(|name| (name.to_string(), Value::from(false)));
+            Value::Object(flags.collect())
+        };
+        let mut permissions = flags(&[
+            "can_send_basic_messages",
+            "can_send_audios",
+            "can_send_documents",
+            "can_send_photos",
+            "can_send_videos",
+            "can_send_video_notes",
+            "can_send_voice_notes",
+            "can_send_polls",
+            "can_send_other_messages",
+            "can_add_link_previews",
+            "can_change_info",
+            "can_invite_users",
+            "can_pin_messages",
+            "can_create_topics",
+        ]);
+        permissions["@type"] = "chatPermissions".into();
+        let mut notification_settings = flags(&[
+            "use_default_mute_for",
+            "use_default_sound",
+            "use_default_show_preview",
+            "show_preview",
+            "use_default_mute_stories",
+            "mute_stories",
+            "use_default_story_sound",
+            "use_default_show_story_sender",
+            "show_story_sender",
+            "use_default_disable_pinned_message_notifications",
+            "disable_pinned_message_notifications",
+            "use_default_disable_mention_notifications",
+            "disable_mention_notifications",
+        ]);
+        notification_settings["@type"] = "chatNotificationSettings".into();
+        for field in ["mute_for", "sound_id", "story_sound_id"] {
+            notification_settings[field] = 0.into();
+        }
+
+        let mut chat = flags(&[
+            "has_protected_content",
+            "is_translatable",
+            "is_marked_as_unread",
+            "view_as_topics",
+            "has_scheduled_messages",
+            "can_be_deleted_only_for_self",
+            "can_be_deleted_for_all_users",
+            "can_be_reported",
+            "default_disable_notification",
+        ]);
+        for field in [
+            "accent_color_id",
+            "background_custom_emoji_id",
+            "profile_accent_color_id",
+            "profile_background_custom_emoji_id",
+            "unread_count",
+            "last_read_inbox_message_id",
+            "last_read_outbox_message_id",
+            "unread_mention_count",
+            "unread_reaction_count",
+            "message_auto_delete_time",
+            "reply_markup_message_id",
+        ] {
+            chat[field] = 0.into();
+        }
+        chat["@type"] = "chat".into();
+        chat["id"] = id.into();
+        chat["type"] = serde_json::json!({ "@type": "chatTypePrivate", "user_id": id });
+        chat["title"] = title.into();
+        chat["permissions"] = permissions;
+        chat["notification_settings"] = notification_settings;
+        chat["available_reactions"] =
+            serde_json::json!({ "@type": "chatAvailableReactionsAll", "max_reaction_count": 11 });
+        chat["video_chat"] = serde_json::json!({
+            "@type": "videoChat",
+            "group_call_id": 0,
+            "has_participants": false,
+        });
+        chat["positions"] = serde_json::json!([]);
+        chat["chat_lists"] = serde_json::json!([]);
+        chat["theme_name"] = "".into();
+        chat["client_data"] = "".into();
+        serde_json::json!({ "@type": "updateNewChat", "chat": chat })
+    };
+    let position = |chat_id: i64, order: i64| {
+        serde_json::json!({
+            "@type": "updateChatPosition",
+            "chat_id": chat_id,
+            "position": {
+                "@type": "chatPosition",
+                "list": { "@type": "chatListMain" },
+                "order": order,
+                "is_pinned": false,
+            },
+        })
+    };
+
+    let transport = MemoryTransport::new();
+    let state = State::new();
+    let driver = DriverBuilder::new()
+        .manager(ClientManager::new(transport.clone()))
+        .state(state.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut updates = driver.subscribe();
+
+    let pushed = [
+        chat(1, "first"),
+        chat(2, "second"),
+        chat(3, "third"),
+        position(1, 10),
+        position(2, 30),
+        position(3, 20),
+        serde_json::json!({ "@type": "updateChatTitle", "chat_id": 2, "title": "renamed" }),
+        position(3, 0),
+    ];
+    let count = pushed.len();
+    for update in pushed {
+        transport.push(driver.id, update);
+    }
+    for _ in 0..count {
+        updates.recv().await.unwrap();
+    }
+
+    assert_eq!(state.chat(2).unwrap().title, "renamed");
+    assert_eq!(state.chat_list(&ChatList::chatListMain), [2, 1]);
+    assert_eq!(
+        state.chat_list(&ChatList::chatListArchive),
+        Vec::<i64>::new()
+    );
+    assert!(state.chat(4).is_none());
+    assert!(driver.state().is_some());
+}
@@ -0,0 +1,104 @@
+use super::*;
+use futures::Stream;
+use futures::StreamExt;
+use std::collections::HashSet;
+use tokio::sync::broadcast;
+
+/// Number of updates buffered per client before slow subscribers start missing updates
+pub const DEFAULT_UPDATE_CAPACITY: usize = 1024;
+
+/// An update as it is passed around between the receive loop and the subscribers
+#[derive(Debug, Clone)]
+pub(crate) struct Event {
+    /// `@type` of the update, e.g. `updateNewMessage`
+    pub(crate) kind: Arc<str>,
+
+    /// The update, deserialized once by the receive loop
+    pub(crate) update: Arc<Update>,
+}
+
+/// Sending half of the update feed of a client
+pub(crate) type UpdateFeed = broadcast::Sender<Event>;
+
+/// # Subscription
+/// Receiving half of the update feed of a [Driver], created by [Driver::subscribe] or
+/// [Driver::subscribe_to]. Every update is deserialized into an [Update] once by the receive loop
+/// and shared between all subscribers.
+/// - [Subscription::recv]: waits for the next update
+/// - [Subscription::into_stream]: turns the subscription into a [Stream]
+///
+/// *Note*: updates are buffered per client (see [DriverBuilder::update_capacity]). A subscriber
+/// that falls further behind than that skips the oldest updates; [Subscription::missed] counts
+/// them.
+/// # Example
+/// ```ignore
+/// let mut messages = driver.subscribe_to(["updateNewMessage"]);
+/// while let Some(update) = messages.recv().await {
+///     if let Update::updateNewMessage(updateNewMessage { message }) = update.as_ref() {
+///         println!("{message:#?}");
+///     }
+/// }
+/// ```
+pub struct Subscription {
+    receiver: broadcast::Receiver<Event>,
+
+    /// `@type`s to let through; all updates if [None]
+    filter: Option<HashSet<String>>,
+
+    /// Number of updates skipped because the subscriber fell behind
+    missed: u64,
+}
+
+impl Subscription {
+    pub(crate) fn new(updates: &UpdateFeed, filter: Option<HashSet<String>>) -> Self {
+        Self {
+            receiver: updates.subscribe(),
+            filter,
+            missed: 0,
+        }
+    }
+
+    /// Waits for the next update that passes the filter. Returns [None] once the [Driver] has
+    /// been dropped.
+    pub async fn recv(&mut self) -> Option<Arc<Update>> {
+        loop {
+            match self.receiver.recv().await {
+                Result::Ok(event) => {
+                    if Self::passes(&self.filter, &event) {
+                        break Some(event.update);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => self.missed += missed,
+                Err(broadcast::error::RecvError::Closed) => break None,
+            }
+        }
+    }
+
+    /// Number of updates this subscriber has skipped because it fell behind
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// Turns the subscription into a [Stream] of updates that passes the filter.
+    ///
+    /// *Note*: updates skipped because the subscriber fell behind are silently left out.
+    pub fn into_stream(self) -> impl Stream<Item = Arc<Update>> + Send + Unpin + 'static {
+        let Self {
+            receiver, filter, ..
+        } = self;
+
+        tokio_stream::wrappers::BroadcastStream::new(receiver).filter_map(move |event| {
+            let update = event
+                .ok()
+                .and_then(|event| Self::passes(&filter, &event).then_some(event.update));
+            std::future::ready(update)
+        })
+    }
+
+    /// Whether `event` passes `filter`
+    fn passes(filter: &Option<HashSet<String>>, event: &Event) -> bool {
+        filter
+            .as_ref()
+            .is_none_or(|filter| filter.contains(event.kind.as_ref()))
+    }
+}
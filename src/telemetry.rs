@@ -0,0 +1,220 @@
+//! # Telemetry
+//! With the `metrics` feature, every [Driver] reports through the [metrics] facade:
+//!
+//! | Metric                             | Kind      | Labels                        |
+//! |------------------------------------|-----------|-------------------------------|
+//! | `tdlib_requests_in_flight`         | gauge     | `client_id`                   |
+//! | `tdlib_requests_total`             | counter   | `client_id`, `method`         |
+//! | `tdlib_request_duration_seconds`   | histogram | `client_id`, `method`         |
+//! | `tdlib_errors_total`               | counter   | `client_id`, `method`, `code` |
+//! | `tdlib_timeouts_total`             | counter   | `client_id`, `method`         |
+//! | `tdlib_packets_dropped_total`      | counter   |                               |
+//! | `tdlib_late_responses_total`       | counter   |                               |
+//! | `tdlib_updates_total`              | counter   | `client_id`, `type`           |
+//! | `tdlib_notifications_dropped_total`| counter   | `reason`                      |
+//!
+//! `client_id` is the TDLib client id of the [Driver], `method` the `@type` of the request payload
+//! and `type` the `@type` of the update. `reason` is `overflow` for notifications discarded by a
+//! full [BoundedNotifier] and `coalesced` for updates replaced by a later one. Nothing is recorded
+//! unless a recorder is installed, e.g. with `serve_prometheus` (`prometheus` feature).
+use super::*;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+const IN_FLIGHT: &str = "tdlib_requests_in_flight";
+#[cfg(feature = "metrics")]
+const REQUESTS: &str = "tdlib_requests_total";
+#[cfg(feature = "metrics")]
+const DURATION: &str = "tdlib_request_duration_seconds";
+#[cfg(feature = "metrics")]
+const ERRORS: &str = "tdlib_errors_total";
+#[cfg(feature = "metrics")]
+const TIMEOUTS: &str = "tdlib_timeouts_total";
+#[cfg(feature = "metrics")]
+const PACKETS_DROPPED: &str = "tdlib_packets_dropped_total";
+#[cfg(feature = "metrics")]
+const LATE_RESPONSES: &str = "tdlib_late_responses_total";
+#[cfg(feature = "metrics")]
+const UPDATES: &str = "tdlib_updates_total";
+#[cfg(feature = "metrics")]
+const NOTIFICATIONS_DROPPED: &str = "tdlib_notifications_dropped_total";
+
+/// Counts a request of the client with `client_id` that is waiting for its response
+pub(crate) fn request_started(client_id: i32) {
+    #[cfg(feature = "metrics")]
+    metrics::gauge!(IN_FLIGHT, "client_id" => client_id.to_string()).increment(1.0);
+    #[cfg(not(feature = "metrics"))]
+    let _ = client_id;
+}
+
+/// Counts a request of the client with `client_id` that no longer waits for its response
+pub(crate) fn request_finished(client_id: i32) {
+    #[cfg(feature = "metrics")]
+    metrics::gauge!(IN_FLIGHT, "client_id" => client_id.to_string()).decrement(1.0);
+    #[cfg(not(feature = "metrics"))]
+    let _ = client_id;
+}
+
+/// Records a completed request of the client with `client_id` to `method` that took `elapsed`
+pub(crate) fn request(
+    client_id: i32,
+    method: &str,
+    elapsed: Duration,
+    result: Result<(), &Failure>,
+) {
+    #[cfg(feature = "metrics")]
+    {
+        let client_id = client_id.to_string();
+        let method = method.to_string();
+        let labels = [("client_id", client_id), ("method", method)];
+        metrics::counter!(REQUESTS, &labels).increment(1);
+        metrics::histogram!(DURATION, &labels).record(elapsed);
+
+        match result.err().map(|failure| (failure.kind(), failure.code())) {
+            Some((ErrorKind::Timeout, _)) => metrics::counter!(TIMEOUTS, &labels).increment(1),
+            Some((_, Some(code))) => {
+                let [client_id, method] = labels;
+                let labels = [client_id, method, ("code", code.to_string())];
+                metrics::counter!(ERRORS, &labels).increment(1)
+            }
+            _ => (),
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (client_id, method, elapsed, result);
+}
+
+/// Counts a [Notification::PacketDropped]
+pub(crate) fn packet_dropped() {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(PACKETS_DROPPED).increment(1);
+}
+
+/// Counts a [Notification::LateResponse]
+pub(crate) fn late_response() {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(LATE_RESPONSES).increment(1);
+}
+
+/// Counts an update of type `kind` for the client with `client_id`
+pub(crate) fn update(client_id: i32, kind: &str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(UPDATES, "client_id" => client_id.to_string(), "type" => kind.to_string())
+        .increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (client_id, kind);
+}
+
+/// Counts a notification discarded by a [BoundedNotifier] for `reason`
+pub(crate) fn notification_dropped(reason: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(NOTIFICATIONS_DROPPED, "reason" => reason).increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = reason;
+}
+
+#[cfg(feature = "metrics")]
+/// Registers the descriptions of the metrics with the installed recorder
+pub fn describe() {
+    metrics::describe_gauge!(IN_FLIGHT, "Requests waiting for a response, by client");
+    metrics::describe_counter!(REQUESTS, "Requests that completed, by client and method");
+    metrics::describe_histogram!(
+        DURATION,
+        metrics::Unit::Seconds,
+        "Time from sending a request to its response, by client and method"
+    );
+    metrics::describe_counter!(ERRORS, "TDLib errors, by client, method and error code");
+    metrics::describe_counter!(TIMEOUTS, "Requests that timed out, by client and method");
+    metrics::describe_counter!(PACKETS_DROPPED, "Responses without a request");
+    metrics::describe_counter!(
+        LATE_RESPONSES,
+        "Responses after their request was abandoned"
+    );
+    metrics::describe_counter!(UPDATES, "Updates received, by client and type");
+    metrics::describe_counter!(
+        NOTIFICATIONS_DROPPED,
+        "Notifications discarded by a bounded notifier, by reason"
+    );
+}
+
+#[cfg(feature = "prometheus")]
+/// Buckets of `tdlib_request_duration_seconds`
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[cfg(feature = "prometheus")]
+/// # Serve Prometheus
+/// Installs a Prometheus recorder as the global [metrics] recorder and serves the metrics in the
+/// Prometheus text format on `http://{address}/metrics`.
+///
+/// *Note*: has to be called from within a [tokio] runtime.
+/// # Example
+/// ```ignore
+/// telemetry::serve_prometheus(([127, 0, 0, 1], 9100).into())?;
+/// ```
+pub fn serve_prometheus(
+    address: std::net::SocketAddr,
+) -> Result<(), metrics_exporter_prometheus::BuildError> {
+    prometheus()?.with_http_listener(address).install()?;
+    describe();
+    Result::Ok(())
+}
+
+#[cfg(feature = "prometheus")]
+/// Prometheus exporter with the buckets of the driver's histograms
+fn prometheus(
+) -> Result<metrics_exporter_prometheus::PrometheusBuilder, metrics_exporter_prometheus::BuildError>
+{
+    metrics_exporter_prometheus::PrometheusBuilder::new().set_buckets_for_metric(
+        metrics_exporter_prometheus::Matcher::Full(DURATION.into()),
+        DURATION_BUCKETS,
+    )
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+/// Requests and their failures are counted per method
+fn telemetry() {
+    let recorder = prometheus().unwrap().build_recorder();
+    let handle = recorder.handle();
+
+    metrics::with_local_recorder(&recorder, || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let transport = MemoryTransport::new();
+            transport.respond_with(|request| match request["@type"].as_str() {
+                Some("getChat") => Some(serde_json::json!({
+                    "@type": "error",
+                    "code": 400,
+                    "message": "CHAT_ID_INVALID",
+                })),
+                _ => None,
+            });
+            let manager = ClientManager::new(transport);
+            let (first, second) = (manager.client(None), manager.client(None));
+
+            let _ = first.getChat(1).await;
+            let _ = second.getChat(1).await;
+            let _ = second.with_timeout(Duration::from_millis(10)).getMe().await;
+        });
+    });
+
+    let rendered = handle.render();
+    for client_id in [1, 2] {
+        let requests =
+            format!(r#"tdlib_requests_total{{client_id="{client_id}",method="getChat"}} 1"#);
+        assert!(rendered.contains(&requests));
+        let errors = format!(
+            r#"tdlib_errors_total{{client_id="{client_id}",method="getChat",code="400"}} 1"#
+        );
+        assert!(rendered.contains(&errors));
+        let in_flight = format!(r#"tdlib_requests_in_flight{{client_id="{client_id}"}} 0"#);
+        assert!(rendered.contains(&in_flight));
+    }
+    assert!(rendered.contains(r#"tdlib_timeouts_total{client_id="2",method="getMe"} 1"#));
+}
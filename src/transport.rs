@@ -0,0 +1,306 @@
+use super::*;
+use std::collections::VecDeque;
+#[cfg(feature = "tdjson")]
+use std::ffi::CStr;
+#[cfg(feature = "tdjson")]
+use std::ffi::CString;
+use std::sync::atomic::AtomicI32;
+use std::sync::Condvar;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+/// # Transport
+/// The JSON interface of TDLib that a [ClientManager] drives its clients through:
+/// - [Tdjson]: the linked `libtdjson`
+/// - [MemoryTransport]: an in-memory backend whose responses are scripted, for tests
+///
+/// *Note*: [Transport::receive] hands out the payloads of every client of the transport, so each
+/// transport must be driven by a single [ClientManager].
+pub trait Transport: Send + Sync + 'static {
+    /// Creates a new client and returns its id
+    fn create_client(&self) -> i32;
+
+    /// Sends the JSON `request` to the client with `client_id`
+    fn send(&self, client_id: i32, request: &str);
+
+    /// Waits up to `timeout` for the next response or update of any client
+    fn receive(&self, timeout: Duration) -> Option<String>;
+
+    /// Executes the JSON `request` synchronously, see [SyncApi]
+    fn execute(&self, request: &str) -> Option<String>;
+}
+
+impl<Shared: Transport + ?Sized> Transport for Arc<Shared> {
+    fn create_client(&self) -> i32 {
+        self.as_ref().create_client()
+    }
+
+    fn send(&self, client_id: i32, request: &str) {
+        self.as_ref().send(client_id, request)
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<String> {
+        self.as_ref().receive(timeout)
+    }
+
+    fn execute(&self, request: &str) -> Option<String> {
+        self.as_ref().execute(request)
+    }
+}
+
+/// Executes `payload` synchronously on `transport`
+pub(crate) fn execute<Payload: Serialize, Target: DeserializeOwned>(
+    transport: &dyn Transport,
+    payload: Payload,
+) -> Result<Target, Failure> {
+    use serde::Deserialize;
+
+    let payload = serde_json::to_string(&payload)?;
+    let result = transport
+        .execute(&payload)
+        .ok_or(Failure::Receiver(ReceiverError::Empty))?;
+    let result = serde_json::from_str::<Value>(&result)?;
+
+    match result["@type"].as_str() {
+        Some("error") => Err(Failure::TdLib(Error::deserialize(result)?)),
+        _ => Result::Ok(Target::deserialize(result)?),
+    }
+}
+
+#[cfg(feature = "tdjson")]
+/// # Tdjson
+/// [Transport] over the JSON interface of the linked `libtdjson`. `td_receive` is process-global,
+/// so [ClientManager::global] is the only manager that should use it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tdjson;
+
+#[cfg(feature = "tdjson")]
+impl Transport for Tdjson {
+    fn create_client(&self) -> i32 {
+        unsafe { tdlib::td_create_client_id() }
+    }
+
+    fn send(&self, client_id: i32, request: &str) {
+        let request = CString::new(request).unwrap();
+        unsafe {
+            tdlib::td_send(client_id, request.as_ptr());
+        }
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<String> {
+        let payload = unsafe { tdlib::td_receive(timeout.as_secs_f64()).as_ref() };
+        payload.map(|ptr| unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string())
+    }
+
+    fn execute(&self, request: &str) -> Option<String> {
+        let request = CString::new(request).unwrap();
+        let result = unsafe { tdlib::td_execute(request.as_ptr()).as_ref() };
+        result.map(|ptr| unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string())
+    }
+}
+
+/// Scripted answer to a request
+type Responder = Arc<dyn Fn(&Value) -> Option<Value> + Send + Sync>;
+
+/// # Memory Transport
+/// In-memory [Transport] that answers requests from a script instead of TDLib, so that the
+/// plumbing of a [Driver] can be tested without `libtdjson`:
+/// - [MemoryTransport::respond_with]: answers to requests
+/// - [MemoryTransport::execute_with]: answers to synchronous requests
+/// - [MemoryTransport::push]: queues an update for a client
+/// - [MemoryTransport::requests]: every request sent so far
+/// # Example
+/// ```ignore
+/// let transport = MemoryTransport::new();
+/// transport.respond_with(|request| match request["@type"].as_str() {
+///     Some("getAuthorizationState") => Some(json!({ "@type": "authorizationStateReady" })),
+///     _ => None,
+/// });
+///
+/// let driver = ClientManager::new(transport.clone()).client(None);
+/// let state = driver.getAuthorizationState().await?;
+/// ```
+#[derive(Default)]
+pub struct MemoryTransport {
+    /// Id of the last client created
+    clients: AtomicI32,
+    memory: Mutex<Memory>,
+    /// Signalled whenever a payload is queued
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct Memory {
+    /// Payloads waiting to be received
+    queue: VecDeque<String>,
+
+    /// Requests sent so far, with the id of the client they were sent to
+    requests: Vec<(i32, Value)>,
+
+    respond: Option<Responder>,
+    execute: Option<Responder>,
+}
+
+impl MemoryTransport {
+    /// Creates a transport that leaves every request unanswered
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Answers every request with the return value of `respond`; requests for which it returns
+    /// [None] are left unanswered. The answer is addressed to the client that sent the request and
+    /// carries its `@extra`.
+    pub fn respond_with<Respond>(&self, respond: Respond)
+    where
+        Respond: Fn(&Value) -> Option<Value> + Send + Sync + 'static,
+    {
+        self.memory().respond = Some(Arc::new(respond));
+    }
+
+    /// Answers every synchronous request with the return value of `execute`
+    pub fn execute_with<Execute>(&self, execute: Execute)
+    where
+        Execute: Fn(&Value) -> Option<Value> + Send + Sync + 'static,
+    {
+        self.memory().execute = Some(Arc::new(execute));
+    }
+
+    /// Queues `payload`, e.g. an update, for the client with `client_id`
+    pub fn push(&self, client_id: i32, mut payload: Value) {
+        payload["@client_id"] = client_id.into();
+        self.push_raw(payload.to_string());
+    }
+
+    /// Queues `payload` as it is, without addressing it to a client
+    pub fn push_raw<Payload: Into<String>>(&self, payload: Payload) {
+        self.memory().queue.push_back(payload.into());
+        self.ready.notify_all();
+    }
+
+    /// Requests sent so far, with the id of the client they were sent to
+    pub fn requests(&self) -> Vec<(i32, Value)> {
+        self.memory().requests.clone()
+    }
+
+    /// Locks the state of the transport
+    fn memory(&self) -> MutexGuard<'_, Memory> {
+        // NOTE: safe to unwrap because poison cleared
+        while self.memory.is_poisoned() {
+            self.memory.clear_poison();
+        }
+        self.memory.lock().unwrap()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn create_client(&self) -> i32 {
+        self.clients.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn send(&self, client_id: i32, request: &str) {
+        let Result::Ok(request) = serde_json::from_str::<Value>(request) else {
+            return;
+        };
+
+        // NOTE: the script runs without the lock held, so that it may push payloads itself
+        let respond = {
+            let mut memory = self.memory();
+            memory.requests.push((client_id, request.clone()));
+            memory.respond.clone()
+        };
+
+        if let Some(mut response) = respond.and_then(|respond| respond(&request)) {
+            if let Some(extra) = request.get("@extra") {
+                response["@extra"] = extra.clone();
+            }
+            self.push(client_id, response);
+        }
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<String> {
+        let memory = self.memory();
+        let (mut memory, _) = self
+            .ready
+            .wait_timeout_while(memory, timeout, |memory| memory.queue.is_empty())
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        memory.queue.pop_front()
+    }
+
+    fn execute(&self, request: &str) -> Option<String> {
+        let request = serde_json::from_str::<Value>(request).ok()?;
+        let execute = self.memory().execute.clone()?;
+        execute(&request).map(|result| result.to_string())
+    }
+}
+
+#[tokio::test]
+/// Responses are delivered to the request that caused them, with the caller's `@extra`
+async fn memory_transport_request() {
+    let transport = MemoryTransport::new();
+    transport.respond_with(|request| match request["@type"].as_str() {
+        Some("getOption") => Some(serde_json::json!({
+            "@type": "optionValueString",
+            "value": "1.8.0",
+        })),
+        _ => None,
+    });
+    let manager = ClientManager::new(transport.clone());
+    let driver = manager.client(None);
+
+    let version = driver.getOption("version".into()).await.unwrap();
+    assert_eq!(String::from(version), "1.8.0");
+
+    let payload = serde_json::json!({ "@type": "getOption", "name": "version", "@extra": "mine" });
+    let response = driver.send::<_, Value>(payload).await.unwrap();
+    assert_eq!(response["@extra"], "mine");
+
+    let (client_id, request) = transport.requests().remove(0);
+    assert_eq!(client_id, driver.id);
+    assert_eq!(request["@type"], "getOption");
+    assert_eq!(driver.pending(), 0);
+}
+
+#[tokio::test]
+/// Updates are routed to the client they are addressed to
+async fn memory_transport_updates() {
+    let transport = MemoryTransport::new();
+    let manager = ClientManager::new(transport.clone());
+    let first = manager.client(None);
+    let second = manager.client(None);
+    let mut first_updates = first.subscribe_to(["updateAuthorizationState"]);
+    let mut second_updates = second.subscribe();
+
+    let update = serde_json::json!({
+        "@type": "updateAuthorizationState",
+        "authorization_state": { "@type": "authorizationStateReady" },
+    });
+    transport.push(first.id, update);
+
+    let update = first_updates.recv().await.unwrap();
+    assert!(matches!(
+        update.as_ref(),
+        Update::updateAuthorizationState(updateAuthorizationState {
+            authorization_state: AuthorizationState::authorizationStateReady,
+        })
+    ));
+
+    let nothing = tokio::time::timeout(Duration::from_millis(50), second_updates.recv()).await;
+    assert!(nothing.is_err());
+}
+
+#[tokio::test]
+/// Unanswered requests time out and leave nothing behind in the task queue
+async fn memory_transport_timeout() {
+    let transport = MemoryTransport::new();
+    let driver = ClientManager::new(transport.clone()).client(None);
+
+    let result = driver
+        .with_timeout(Duration::from_millis(50))
+        .getAuthorizationState()
+        .await;
+    assert!(matches!(
+        result,
+        Err(Failure::Receiver(ReceiverError::Timeout(_)))
+    ));
+    assert_eq!(driver.pending(), 0);
+}
@@ -0,0 +1,446 @@
+use super::*;
+use crate::file_watch::*;
+use futures::Stream;
+use std::path::PathBuf;
+
+/// Priority of uploads when nothing else has been configured
+pub const DEFAULT_UPLOAD_PRIORITY: i32 = 1;
+
+/// Source of spooled file names, unique within the process
+static SPOOL_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// # Upload Source
+/// What to upload: a file on disk, or bytes in memory that are first spooled to a temporary file
+/// named after the file name in `name`. Both convert from the obvious types.
+#[derive(Debug, Clone)]
+pub enum UploadSource {
+    Path(PathBuf),
+    Bytes { name: String, bytes: Vec<u8> },
+}
+
+impl UploadSource {
+    /// Bytes to be spooled to a temporary file named after `name`, e.g. `photo.jpg`
+    pub fn bytes<Name: Into<String>>(name: Name, bytes: Vec<u8>) -> Self {
+        Self::Bytes {
+            name: name.into(),
+            bytes,
+        }
+    }
+}
+
+impl From<PathBuf> for UploadSource {
+    fn from(value: PathBuf) -> Self {
+        Self::Path(value)
+    }
+}
+
+impl From<&std::path::Path> for UploadSource {
+    fn from(value: &std::path::Path) -> Self {
+        Self::Path(value.to_path_buf())
+    }
+}
+
+impl From<&str> for UploadSource {
+    fn from(value: &str) -> Self {
+        Self::Path(value.into())
+    }
+}
+
+/// Progress of an [Upload]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadProgress {
+    /// Bytes uploaded so far
+    pub uploaded: i64,
+
+    /// Expected size of the file in bytes; 0 if unknown
+    pub expected: i64,
+}
+
+impl UploadProgress {
+    fn new(file: &file) -> Self {
+        Self {
+            uploaded: file.remote.uploaded_size,
+            expected: file.expected_size.max(file.size),
+        }
+    }
+
+    /// Share of the file uploaded so far, between 0 and 1; [None] if the size is unknown
+    pub fn fraction(&self) -> Option<f64> {
+        (self.expected > 0).then(|| (self.uploaded as f64 / self.expected as f64).min(1.0))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Errors of an [Upload]
+pub enum UploadError {
+    /// The [Upload] was dropped before the upload completed
+    Cancelled,
+
+    /// TDLib stopped uploading the file before it was complete
+    Interrupted,
+
+    /// The [Driver] went away before the upload completed
+    Closed,
+
+    /// The bytes could not be spooled to a temporary file
+    Io(Arc<std::io::Error>),
+
+    /// A request failed, e.g. `preliminaryUploadFile` for a path that does not exist
+    Failure(Arc<Failure>),
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "upload cancelled"),
+            Self::Interrupted => write!(f, "upload stopped before it was complete"),
+            Self::Closed => write!(f, "client is closed"),
+            Self::Io(err) => write!(f, "failed to spool upload: {err}"),
+            Self::Failure(failure) => write!(f, "{failure}"),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err.as_ref()),
+            Self::Failure(failure) => Some(failure.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Failure> for UploadError {
+    fn from(value: Failure) -> Self {
+        Self::Failure(Arc::new(value))
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(Arc::new(value))
+    }
+}
+
+/// `updateFile`s of an upload, as followed by a [FileWatch]. A sent message that contains the
+/// file also completes the upload, and the spooled file, if any, is removed then.
+struct Uploading {
+    spooled: Option<PathBuf>,
+}
+
+impl Transfer for Uploading {
+    type Progress = UploadProgress;
+    type Error = UploadError;
+
+    const CANCELLED: UploadError = UploadError::Cancelled;
+    const INTERRUPTED: UploadError = UploadError::Interrupted;
+    const CLOSED: UploadError = UploadError::Closed;
+
+    // NOTE: the upload may not have started yet right after `preliminaryUploadFile`
+    const STARTS_IDLE: bool = true;
+
+    fn progress(file: &file) -> UploadProgress {
+        UploadProgress::new(file)
+    }
+
+    fn is_completed(file: &file) -> bool {
+        file.remote.is_uploading_completed
+    }
+
+    fn is_active(file: &file) -> bool {
+        file.remote.is_uploading_active
+    }
+
+    async fn cancel(&self, driver: &Driver, file_id: i32) -> Result<(), Failure> {
+        driver
+            .cancelPreliminaryUploadFile(file_id)
+            .await
+            .map(|_| ())
+    }
+
+    async fn resolve(
+        &self,
+        driver: &Driver,
+        update: &Update,
+        file_id: i32,
+    ) -> Result<Option<file>, Failure> {
+        let Update::updateMessageSendSucceeded(update) = update else {
+            return Result::Ok(None);
+        };
+        if !serde_json::to_value(&update.message).is_ok_and(|message| contains(&message, file_id)) {
+            return Result::Ok(None);
+        }
+
+        // NOTE: the message could only be sent once the file was uploaded, and TDLib no longer
+        // needs the spooled file once it has been sent
+        if let Some(path) = &self.spooled {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        let File::file(mut file) = driver.getFile(file_id).await?;
+        file.remote.is_uploading_completed = true;
+        Result::Ok(Some(file))
+    }
+}
+
+/// Whether `value` contains the file with `file_id`, at any depth
+fn contains(value: &Value, file_id: i32) -> bool {
+    match value {
+        Value::Object(object) => {
+            (object.get("@type").is_some_and(|kind| kind == "file")
+                && object.get("id").is_some_and(|id| id == file_id))
+                || object.values().any(|value| contains(value, file_id))
+        }
+        Value::Array(values) => values.iter().any(|value| contains(value, file_id)),
+        _ => false,
+    }
+}
+
+/// # Upload
+/// A file being uploaded ahead of sending it, started with [Upload::start]. `preliminaryUploadFile`
+/// only starts the upload; its progress then trickles in as `updateFile`. The upload follows those
+/// updates, so that it can report its [UploadProgress] and resolve once TDLib reports the file as
+/// uploaded, either through `updateFile` or through `updateMessageSendSucceeded` of a message
+/// that contains it.
+///
+/// *Note*: dropping an [Upload] before it has completed cancels it with
+/// `cancelPreliminaryUploadFile`, so keep it around until the message has been sent. A spooled
+/// temporary file is removed once a message containing it has been sent, or when the [Upload] is
+/// dropped.
+/// # Example
+/// ```ignore
+/// let upload = Upload::start(driver.clone(), UploadSource::bytes("photo.jpg", bytes), None).await?;
+///
+/// let content = InputMessageContent::inputMessagePhoto(inputMessagePhoto {
+///     photo: upload.input_file(),
+///     ..
+/// });
+/// driver.sendMessage(chat_id, 0, None, None, None, content).await?;
+/// upload.wait().await?;
+/// ```
+pub struct Upload {
+    file_id: i32,
+    watch: FileWatch<Uploading>,
+    _spool: Option<Spool>,
+}
+
+/// Temporary file that bytes are spooled to, removed when dropped
+struct Spool(PathBuf);
+
+impl Spool {
+    /// Unique path in the temporary directory, ending in the file name of `name` if it has one
+    fn new(name: &str) -> Self {
+        let mut file_name = format!(
+            "tdlib-upload-{}-{}",
+            std::process::id(),
+            SPOOL_ID.fetch_add(1, Ordering::Relaxed),
+        );
+        // NOTE: only the file name is kept, so that `name` cannot point outside the directory
+        if let Some(name) = std::path::Path::new(name).file_name() {
+            file_name.push('-');
+            file_name.push_str(&name.to_string_lossy());
+        }
+        Self(std::env::temp_dir().join(file_name))
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl Upload {
+    /// # Start
+    /// Starts uploading `source`, spooling it to a temporary file first if it is in memory.
+    /// Arguments:
+    /// - `driver`: client to upload with
+    /// - `source`: what to upload
+    /// - `file_type`: type of the file, which TDLib otherwise guesses from its contents
+    pub async fn start<Source: Into<UploadSource>>(
+        driver: Arc<Driver>,
+        source: Source,
+        file_type: Option<FileType>,
+    ) -> Result<Self, UploadError> {
+        let (path, spool) = match source.into() {
+            UploadSource::Path(path) => (path, None),
+            UploadSource::Bytes { name, bytes } => {
+                let spool = Spool::new(&name);
+                tokio::fs::write(&spool.0, bytes).await?;
+                (spool.0.clone(), Some(spool))
+            }
+        };
+
+        // Subscribe before uploading so that no update is missed
+        let updates = driver.subscribe_to(["updateFile", "updateMessageSendSucceeded"]);
+        let input = InputFile::inputFileLocal(inputFileLocal {
+            path: path.to_string_lossy().into_owned(),
+        });
+        let File::file(file) = driver
+            .preliminaryUploadFile(input, file_type, DEFAULT_UPLOAD_PRIORITY)
+            .await?;
+
+        let file_id = file.id;
+        let (watch, mut tracker) = FileWatch::new(Status::Running(UploadProgress::new(&file)));
+        let uploading = Uploading {
+            spooled: spool.as_ref().map(|spool| spool.0.clone()),
+        };
+        tokio::spawn(async move {
+            let follow = tracker.follow(&uploading, &driver, file, updates);
+            if let Err(err) = follow.await {
+                tracker.fail(err);
+            }
+        });
+
+        Result::Ok(Self {
+            file_id,
+            watch,
+            _spool: spool,
+        })
+    }
+
+    /// Id of the file being uploaded
+    pub fn file_id(&self) -> i32 {
+        self.file_id
+    }
+
+    /// The file as an [InputFile], to be used in an `InputMessageContent`
+    pub fn input_file(&self) -> InputFile {
+        InputFile::inputFileId(inputFileId { id: self.file_id })
+    }
+
+    /// Latest progress of the upload
+    pub fn current(&self) -> UploadProgress {
+        self.watch.current()
+    }
+
+    /// # Progress
+    /// [Stream] of the progress of the upload, starting with the latest. Intermediate progress is
+    /// skipped if the stream is not polled in time. The stream ends once the upload has completed
+    /// or failed.
+    pub fn progress(&self) -> impl Stream<Item = UploadProgress> + Send + Unpin + 'static {
+        self.watch.progress()
+    }
+
+    /// # Wait
+    /// Waits for the upload to complete and returns the uploaded file
+    pub async fn wait(&self) -> Result<file, UploadError> {
+        self.watch.wait().await
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // NOTE: a completed upload ignores the signal, as nobody is listening for it any more
+        self.watch.cancel();
+    }
+}
+
+#[tokio::test]
+/// Bytes are spooled and uploaded, and a dropped upload is cancelled
+async fn upload() {
+    use futures::StreamExt;
+
+    let file = |id: i32, uploaded: i64| {
+        file_json(
+            id,
+            100,
+            Transferred::idle(100),
+            Transferred::active(uploaded),
+        )
+    };
+
+    let transport = MemoryTransport::new();
+    let spooled = Arc::new(Mutex::new(Vec::new()));
+    transport.respond_with({
+        let spooled = spooled.clone();
+        move |request| match request["@type"].as_str() {
+            Some("preliminaryUploadFile") => {
+                let path = request["file"]["path"].as_str()?;
+                spooled.lock().unwrap().push(std::fs::read(path).ok()?);
+                Some(file(spooled.lock().unwrap().len() as i32, 0))
+            }
+            Some("cancelPreliminaryUploadFile") => Some(serde_json::json!({ "@type": "ok" })),
+            _ => None,
+        }
+    });
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+
+    // Uploaded from memory
+    let upload = Upload::start(
+        driver.clone(),
+        UploadSource::bytes("a.txt", b"abc".to_vec()),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(spooled.lock().unwrap().as_slice(), [b"abc".to_vec()]);
+    assert_eq!(
+        upload.input_file(),
+        InputFile::inputFileId(inputFileId { id: 1 })
+    );
+    let mut progress = upload.progress();
+    for uploaded in [40, 100] {
+        let update = serde_json::json!({ "@type": "updateFile", "file": file(1, uploaded) });
+        transport.push(driver.id, update);
+    }
+    assert!(upload.wait().await.unwrap().remote.is_uploading_completed);
+    let progress: Vec<_> = (&mut progress)
+        .map(|progress| progress.uploaded)
+        .collect()
+        .await;
+    assert_eq!(progress.last(), Some(&100));
+    let spool = upload._spool.as_ref().unwrap().0.clone();
+    assert!(spool.exists());
+    std::mem::drop(upload);
+    assert!(!spool.exists());
+
+    // Cancelled on drop
+    let path = std::env::temp_dir().join(format!("tdlib-driver-upload-{}", std::process::id()));
+    std::fs::write(&path, b"def").unwrap();
+    let upload = Upload::start(driver.clone(), path.as_path(), None)
+        .await
+        .unwrap();
+    std::mem::drop(upload);
+    while transport.requests().len() < 3 {
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    assert_eq!(
+        transport.requests()[2].1["@type"],
+        "cancelPreliminaryUploadFile"
+    );
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+/// Spooled files stay in the temporary directory and are removed when the upload is dropped
+async fn upload_spool() {
+    let transport = MemoryTransport::new();
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    transport.respond_with({
+        let paths = paths.clone();
+        move |request| match request["@type"].as_str() {
+            Some("preliminaryUploadFile") => {
+                let path = request["file"]["path"].as_str()?;
+                paths.lock().unwrap().push(PathBuf::from(path));
+                Some(serde_json::json!({ "@type": "error", "code": 400, "message": "FAILED" }))
+            }
+            _ => None,
+        }
+    });
+    let driver = Arc::new(ClientManager::new(transport.clone()).client(None));
+
+    for name in ["../../a.txt", "nested/a.txt", ".."] {
+        let source = UploadSource::bytes(name, b"abc".to_vec());
+        assert!(Upload::start(driver.clone(), source, None).await.is_err());
+    }
+    let paths = paths.lock().unwrap().clone();
+    assert_eq!(paths.len(), 3);
+    for path in &paths {
+        assert_eq!(path.parent(), Some(std::env::temp_dir().as_path()));
+        assert!(!path.exists());
+    }
+    assert!(paths[0].to_string_lossy().ends_with("-a.txt"));
+    assert!(paths[1].to_string_lossy().ends_with("-a.txt"));
+    assert!(!paths[2].to_string_lossy().ends_with(".."));
+}
@@ -422,20 +422,38 @@ pub struct Function {
     pub result_type: String,
 }
 
-impl std::fmt::Display for Function {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// Marker in the description of functions that can be called through `td_execute`
+const SYNC_MARKER: &str = "Can be called synchronously";
+
+/// Signature of a [Function] as it is written into a trait
+struct Signature {
+    /// Doc comment of the method
+    metadata: String,
+
+    /// Doc comments of the arguments
+    comments: String,
+
+    /// Arguments of the method
+    arguments: String,
+
+    /// Fields of the `json!` payload
+    value_fields: String,
+}
+
+impl Function {
+    /// Whether the function is documented as callable synchronously, i.e. through `td_execute`
+    pub fn is_sync(&self) -> bool {
+        self.meta
+            .get("description")
+            .is_some_and(|description| description.contains(SYNC_MARKER))
+    }
+
+    /// Compiles the parts of the method that are shared by [Function] and [SyncFunction]
+    fn signature(&self) -> Signature {
         let Self {
-            name,
-            meta,
-            fields,
-            result_type,
+            name, meta, fields, ..
         } = self;
 
-        // Write permissions
-        writeln!(
-            f,
-            "#[allow(async_fn_in_trait, non_snake_case, non_camel_case_types)]"
-        )?;
         // write metadata
         let metadata = format!(
             "/// # {name}{}",
@@ -489,6 +507,33 @@ impl std::fmt::Display for Function {
             .collect::<Vec<_>>()
             .join("");
 
+        Signature {
+            metadata,
+            comments,
+            arguments,
+            value_fields,
+        }
+    }
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            name, result_type, ..
+        } = self;
+        let Signature {
+            metadata,
+            comments,
+            arguments,
+            value_fields,
+        } = self.signature();
+
+        // Write permissions
+        writeln!(
+            f,
+            "#[allow(async_fn_in_trait, non_snake_case, non_camel_case_types)]"
+        )?;
+
         writeln!(
             f,
             r#"
@@ -510,6 +555,52 @@ impl std::fmt::Display for Function {
     }
 }
 
+/// # SyncFunction
+/// A [Function] that can be called synchronously, written as a blocking method of the `SyncApi`
+/// trait
+pub struct SyncFunction<'function>(pub &'function Function);
+
+impl<'function> std::fmt::Display for SyncFunction<'function> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(function) = self;
+        let Function {
+            name, result_type, ..
+        } = function;
+        let Signature {
+            metadata,
+            comments,
+            arguments,
+            value_fields,
+        } = function.signature();
+
+        // Skip the lines of undocumented arguments, so that no blank line ends the doc comment
+        let docs = std::iter::once(metadata.as_str())
+            .chain(comments.lines().filter(|line| !line.is_empty()))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Write permissions right above the method, with no blank line in between
+        writeln!(
+            f,
+            r#"
+    {docs}
+    #[allow(non_snake_case, non_camel_case_types)]
+    fn {name}(&self{arguments}) -> ::std::result::Result<{result_type}, crate::Failure> {{
+        // Create payload
+        let payload = json!({{
+            "@type": "{name}"{value_fields}
+        }});
+    
+        // Execute payload
+        <Self as SyncApi>::execute::<Value, {result_type}>(self, payload)
+    }}
+"#
+        )?;
+
+        Ok(())
+    }
+}
+
 impl Function {
     /// Obtain reference to the static store of [OBJECTS]
     fn store() -> &'static Arc<RwLock<Vec<Self>>> {
@@ -532,6 +623,37 @@ impl Function {
     }
 }
 
+#[test]
+fn sync_function() {
+    let function = |description: &str| Function {
+        name: "getTextEntities".to_string(),
+        meta: HashMap::from([
+            ("description".to_string(), description.to_string()),
+            (
+                "text".to_string(),
+                "The text in which to look for entities".to_string(),
+            ),
+        ]),
+        fields: vec![ObjectValue {
+            key: "text".to_string(),
+            type_id: "::std::string::String".to_string(),
+        }],
+        result_type: "textEntities".to_string(),
+    };
+
+    let sync = function("Returns all entities found in the text. Can be called synchronously");
+    assert!(sync.is_sync());
+    assert!(!function("Returns all entities found in the text").is_sync());
+
+    let output = SyncFunction(&sync).to_string();
+    assert!(output.contains(
+        "fn getTextEntities(&self, text: ::std::string::String) -> ::std::result::Result<textEntities, crate::Failure>"
+    ));
+    assert!(output.contains("<Self as SyncApi>::execute::<Value, textEntities>(self, payload)"));
+    assert!(output.contains("#[allow(non_snake_case, non_camel_case_types)]\n    fn getTextEntities"));
+    assert!(!output.contains("async"));
+}
+
 #[cfg(test)]
 pub fn harness<Callback: std::future::Future>(
     future: Callback,
@@ -313,6 +313,37 @@ pub trait Api {{
             // End trait
             writeln!(output_stream, r#"}}"#).map_err(|e| format!("WRITE ERROR: {e:#?}"))?;
 
+            // Write synchronous functions
+            // Write trait
+            writeln!(
+                output_stream,
+                r#"
+pub trait SyncApi {{
+
+    /// Handler to execute the `payload` synchronously through `td_execute`
+    fn execute<Payload: Serialize, Target: DeserializeOwned>(
+        &self,
+        payload: Payload,
+    ) -> ::std::result::Result<Target, crate::Failure>;
+"#
+            )
+            .map_err(|e| format!("WRITE ERROR: {e:#?}"))?;
+
+            // Iterate over functions that can be called synchronously
+            for function in Function::read()
+                .iter()
+                .filter(|function| function.is_sync())
+            {
+                let function = SyncFunction(function);
+                write!(output_stream, "{function}").map_err(|e| format!("WRITE ERROR: {e:#?}"))?;
+                output_stream
+                    .flush()
+                    .map_err(|e| format!("WRITE ERROR: {e:#?}"))?;
+            }
+
+            // End trait
+            writeln!(output_stream, r#"}}"#).map_err(|e| format!("WRITE ERROR: {e:#?}"))?;
+
             // return ok
             Ok(())
         }